@@ -0,0 +1,96 @@
+/// on-disk cache for results of compiler invocations. Every entry is a JSON
+/// file named after the hash of its key. The key contains the identity of the
+/// compiler binary, so entries get invalidated when the compiler changes.
+pub struct DiskCache {
+    dir: std::path::PathBuf,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct DiskCacheEntry<T> {
+    key: Vec<String>,
+    value: T,
+}
+
+impl DiskCache {
+    /// `$XDG_CACHE_HOME/cdbpatch` or `~/.cache/cdbpatch`
+    pub fn default_dir() -> Option<std::path::PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(std::path::PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".cache"))
+            })
+            .map(|dir| dir.join("cdbpatch"))
+    }
+
+    pub fn new(dir: std::path::PathBuf) -> Result<Self, anyhow::Error> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// returns the identity of a compiler: its resolved path, mtime and size.
+    /// Compilers without a directory get searched in `PATH`.
+    pub fn compiler_identity(compiler: &str) -> Option<Vec<String>> {
+        let path = std::path::Path::new(compiler);
+        let path = if path.components().count() > 1 {
+            path.to_path_buf()
+        } else {
+            std::env::split_paths(&std::env::var_os("PATH")?)
+                .map(|dir| dir.join(path))
+                .find(|path| path.is_file())?
+        };
+        let path = path.canonicalize().ok()?;
+        let metadata = path.metadata().ok()?;
+        let mtime = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?;
+
+        Some(vec![
+            path.to_string_lossy().to_string(),
+            mtime.as_nanos().to_string(),
+            metadata.len().to_string(),
+        ])
+    }
+
+    fn path(&self, key: &[String]) -> std::path::PathBuf {
+        use std::hash::{Hash as _, Hasher as _};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.json", hasher.finish()))
+    }
+
+    pub fn get<T: serde::de::DeserializeOwned>(&self, key: &[String]) -> Option<T> {
+        let data = std::fs::read_to_string(self.path(key)).ok()?;
+        let entry: DiskCacheEntry<T> = serde_json::from_str(&data).ok()?;
+
+        // protect against hash collisions
+        if entry.key != key {
+            return None;
+        }
+
+        Some(entry.value)
+    }
+
+    pub fn put<T: serde::Serialize>(&self, key: &[String], value: &T) -> Result<(), anyhow::Error> {
+        let path = self.path(key);
+        let entry = DiskCacheEntry {
+            key: key.to_vec(),
+            value,
+        };
+
+        // other threads or processes might write the same entry at the same
+        // time
+        let tmp = path.with_extension(format!(
+            "json.{}.{:?}.tmp",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&tmp, serde_json::to_string(&entry)?)?;
+        std::fs::rename(&tmp, &path)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,462 @@
+use crate::options::Driver;
+
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// single escaped `command` string
+    Command,
+    /// `arguments` array
+    Arguments,
+    /// whichever form the input entry used
+    Preserve,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "command" => Ok(Self::Command),
+            "arguments" => Ok(Self::Arguments),
+            "preserve" => Ok(Self::Preserve),
+            _ => Err(anyhow::anyhow!("unsupported output format: {}", s)),
+        }
+    }
+}
+
+/// how the `command` of an entry is split into arguments and escaped
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommandSyntax {
+    /// POSIX shell rules
+    Gnu,
+    /// the rules of `CommandLineToArgvW`
+    Windows,
+    /// Windows rules for `cl` and `clang-cl`, GNU rules otherwise
+    Auto,
+}
+
+impl std::str::FromStr for CommandSyntax {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gnu" => Ok(Self::Gnu),
+            "windows" => Ok(Self::Windows),
+            "auto" => Ok(Self::Auto),
+            _ => Err(anyhow::anyhow!("unsupported command syntax: {}", s)),
+        }
+    }
+}
+
+/// which entries are kept if several merged databases contain the same file
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MergePolicy {
+    /// the entries of the first database containing the file
+    First,
+    /// the entries of the last database containing the file
+    Last,
+    /// the entries of all databases
+    All,
+}
+
+impl std::str::FromStr for MergePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "all" => Ok(Self::All),
+            _ => Err(anyhow::anyhow!("unsupported merge policy: {}", s)),
+        }
+    }
+}
+
+/// which entry is kept if the same file is compiled several times within the
+/// same directory
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DedupPolicy {
+    First,
+    Last,
+    /// the first one using `-c`
+    Compile,
+    /// the first one with the most `-D` options
+    MostDefines,
+}
+
+impl std::str::FromStr for DedupPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "compile" => Ok(Self::Compile),
+            "most-defines" => Ok(Self::MostDefines),
+            _ => Err(anyhow::anyhow!("unsupported dedup policy: {}", s)),
+        }
+    }
+}
+
+/// an entry of a compilation database
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct CdbEntry {
+    pub directory: String,
+    pub file: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// fields we don't know about, written back untouched
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl CdbEntry {
+    /// returns the unescaped compiler arguments, no matter if the entry uses
+    /// `command` or `arguments`
+    pub fn args(&self, syntax: CommandSyntax) -> Result<Vec<String>, anyhow::Error> {
+        match (&self.arguments, &self.command, syntax) {
+            (Some(arguments), _, _) => Ok(arguments.clone()),
+            (None, Some(command), CommandSyntax::Gnu) => Ok(shellwords::split(command)?),
+            (None, Some(command), CommandSyntax::Windows) => Ok(split_windows(command)),
+            (None, Some(command), CommandSyntax::Auto) => {
+                // the compiler and `--driver-mode=` look the same using
+                // either rules in practice, so they can be used to detect
+                // the driver
+                let args = split_windows(command);
+                if !args.is_empty() && Driver::detect(&args) == Driver::Cl {
+                    Ok(args)
+                } else {
+                    Ok(shellwords::split(command)?)
+                }
+            }
+            (None, None, _) => Err(anyhow::anyhow!(
+                "entry for {} has neither `command` nor `arguments`",
+                self.file
+            )),
+        }
+    }
+
+    /// stores the arguments using the requested form. The other form gets
+    /// removed so the entry stays unambiguous.
+    pub fn set_args(&mut self, args: Vec<String>, format: OutputFormat, syntax: CommandSyntax) {
+        let use_arguments = match format {
+            OutputFormat::Command => false,
+            OutputFormat::Arguments => true,
+            OutputFormat::Preserve => self.arguments.is_some(),
+        };
+
+        if use_arguments {
+            self.command = None;
+            self.arguments = Some(args);
+        } else {
+            let windows = match syntax {
+                CommandSyntax::Gnu => false,
+                CommandSyntax::Windows => true,
+                CommandSyntax::Auto => !args.is_empty() && Driver::detect(&args) == Driver::Cl,
+            };
+            let escape = if windows { escape_windows } else { escape };
+
+            self.arguments = None;
+            self.command = Some(
+                args.iter()
+                    .map(|arg| escape(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+        }
+    }
+}
+
+/// escape command for compilation database
+/// - only " and \ are special within double quotes
+/// - add quotes if there are escape sequences, whitespace or single quotes
+pub fn escape(input: &str) -> String {
+    lazy_static::lazy_static! {
+        static ref ESCAPE_PATTERN: regex::Regex = regex::Regex::new(r#"([\\"])"#).unwrap();
+    }
+
+    if input.is_empty() {
+        return "\"\"".to_owned();
+    }
+
+    let output = ESCAPE_PATTERN.replace_all(input, "\\$1").to_string();
+    if output != input || output.contains(|c: char| c.is_whitespace() || c == '\'') {
+        return format!("\"{output}\"");
+    }
+
+    output
+}
+
+/// splits a command line like `CommandLineToArgvW` does
+pub fn split_windows(input: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut arg = None::<String>;
+    let mut quoted = false;
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' if !quoted => {
+                ret.extend(arg.take());
+            }
+            '\\' => {
+                let mut n = 1;
+                while chars.peek() == Some(&'\\') {
+                    chars.next();
+                    n += 1;
+                }
+
+                // backslashes are only special in front of a quote
+                let arg = arg.get_or_insert_with(String::new);
+                if chars.peek() == Some(&'"') {
+                    arg.extend(std::iter::repeat_n('\\', n / 2));
+                    if n % 2 == 1 {
+                        chars.next();
+                        arg.push('"');
+                    }
+                } else {
+                    arg.extend(std::iter::repeat_n('\\', n));
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                // `""` within quotes is a literal quote
+                if quoted && chars.peek() == Some(&'"') {
+                    chars.next();
+                    arg.push('"');
+                } else {
+                    quoted = !quoted;
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    ret.extend(arg);
+
+    ret
+}
+
+/// escapes an argument so `split_windows` returns it unchanged
+pub fn escape_windows(input: &str) -> String {
+    if !input.is_empty() && !input.contains([' ', '\t', '"']) {
+        return input.to_string();
+    }
+
+    let mut output = String::from("\"");
+    let mut backslashes = 0;
+    for c in input.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            // the backslashes in front of a quote have to be escaped as well
+            '"' => {
+                output.extend(std::iter::repeat_n('\\', backslashes + 1));
+                backslashes = 0;
+            }
+            _ => backslashes = 0,
+        }
+        output.push(c);
+    }
+    // same for the closing quote
+    output.extend(std::iter::repeat_n('\\', backslashes));
+    output.push('"');
+
+    output
+}
+
+/// reads a compilation database
+pub fn load(path: &str) -> Result<Vec<CdbEntry>, anyhow::Error> {
+    Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+}
+
+/// reads the compilation databases matching glob patterns. Patterns without
+/// any matches are an error, the matches of a pattern are sorted.
+pub fn load_all(patterns: &[String]) -> Result<Vec<Vec<CdbEntry>>, anyhow::Error> {
+    let mut ret = Vec::new();
+
+    for pattern in patterns {
+        // plain paths are used as they are, so a missing file gets reported
+        // as such
+        let paths = if pattern.contains(['*', '?', '[']) {
+            let mut paths = glob::glob(pattern)?.collect::<Result<Vec<_>, _>>()?;
+            if paths.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "no compilation database matches {}",
+                    pattern
+                ));
+            }
+            paths.sort();
+            paths
+                .into_iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect()
+        } else {
+            vec![pattern.clone()]
+        };
+
+        for path in paths {
+            ret.push(load(&path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?);
+        }
+    }
+
+    Ok(ret)
+}
+
+/// merges compilation databases. Files are compared after making them
+/// absolute using the directory of their entry.
+pub fn merge(cdbs: Vec<Vec<CdbEntry>>, policy: MergePolicy) -> Vec<CdbEntry> {
+    let files: Vec<std::collections::HashSet<String>> = cdbs
+        .iter()
+        .map(|cdb| {
+            cdb.iter()
+                .map(|entry| crate::paths::absolutize(&entry.directory, &entry.file))
+                .collect()
+        })
+        .collect();
+
+    let mut ret = Vec::new();
+    for (i, cdb) in cdbs.into_iter().enumerate() {
+        // the databases which take precedence over this one
+        let others = match policy {
+            MergePolicy::First => &files[..i],
+            MergePolicy::Last => &files[i + 1..],
+            MergePolicy::All => &[],
+        };
+        ret.extend(cdb.into_iter().filter(|entry| {
+            let file = crate::paths::absolutize(&entry.directory, &entry.file);
+            !others.iter().any(|files| files.contains(&file))
+        }));
+    }
+
+    ret
+}
+
+/// removes entries with the same file and directory except the one chosen by
+/// `policy`. The kept entries stay in place, the removed ones are returned.
+pub fn dedup(
+    cdb: Vec<CdbEntry>,
+    policy: DedupPolicy,
+    syntax: CommandSyntax,
+) -> (Vec<CdbEntry>, Vec<CdbEntry>) {
+    use std::collections::HashMap;
+
+    let rank = |entry: &CdbEntry| {
+        let args = entry.args(syntax).unwrap_or_default();
+        if args.is_empty() {
+            return 0;
+        }
+        let command = crate::options::Command::parse(&args);
+        match policy {
+            DedupPolicy::First | DedupPolicy::Last => 0,
+            DedupPolicy::Compile => command.compiles() as usize,
+            DedupPolicy::MostDefines => command.defines().count(),
+        }
+    };
+
+    // the index and rank of the best entry so far for every file
+    let mut best = HashMap::<(String, String), (usize, usize)>::new();
+    for (i, entry) in cdb.iter().enumerate() {
+        let key = (
+            crate::paths::normalize(&entry.directory),
+            crate::paths::absolutize(&entry.directory, &entry.file),
+        );
+        let rank = rank(entry);
+        best.entry(key)
+            .and_modify(|best| {
+                if rank > best.1 || (rank == best.1 && policy == DedupPolicy::Last) {
+                    *best = (i, rank);
+                }
+            })
+            .or_insert((i, rank));
+    }
+
+    let mut keep = vec![false; cdb.len()];
+    for (i, _) in best.into_values() {
+        keep[i] = true;
+    }
+
+    let (kept, dropped): (Vec<_>, Vec<_>) = cdb.into_iter().zip(keep).partition(|(_, keep)| *keep);
+    (
+        kept.into_iter().map(|(entry, _)| entry).collect(),
+        dropped.into_iter().map(|(entry, _)| entry).collect(),
+    )
+}
+
+/// writes a compilation database
+pub fn save(path: &str, cdb: &[CdbEntry]) -> Result<(), anyhow::Error> {
+    use std::io::Write as _;
+
+    let mut out = std::fs::File::create(path)?;
+    out.write_all(serde_json::to_string(cdb)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(arg: &str) {
+        assert_eq!(split_windows(&escape_windows(arg)), [arg], "{:?}", arg);
+    }
+
+    #[test]
+    fn windows_round_trip() {
+        for arg in [
+            "",
+            "foo",
+            "with space",
+            "tab\there",
+            r"C:\path\to\dir",
+            r"C:\path with space\",
+            r"trailing\\",
+            r#"-DSTR="value""#,
+            r#"-DSTR=\"value\""#,
+            r#"back\\"quote"#,
+            r#"""#,
+            r#""""#,
+            r#"a "" b"#,
+        ]
+        .iter()
+        {
+            round_trip(arg);
+        }
+    }
+
+    #[test]
+    fn gnu_round_trip() {
+        let args = [
+            "gcc",
+            "",
+            "with space",
+            "tab\there",
+            "new\nline",
+            "-DX=it's",
+            "-DCHAR='a'",
+            r#"-DSTR="value""#,
+            r#"-DSTR=\"value\""#,
+            r"C:\path\",
+            r"trailing\\",
+            "$HOME",
+        ];
+        let command = args.iter().map(|arg| escape(arg)).collect::<Vec<_>>();
+        assert_eq!(shellwords::split(&command.join(" ")).unwrap(), args);
+        assert_eq!(escape("-DX=it's"), r#""-DX=it's""#);
+        assert_eq!(escape("-O2"), "-O2");
+    }
+
+    #[test]
+    fn split_windows_rules() {
+        assert_eq!(
+            split_windows(r#"cl "a b" c\d e\\"f g" h\\\"i "j""k""#),
+            ["cl", "a b", r"c\d", r"e\f g", r#"h\"i"#, r#"j"k"#]
+        );
+        assert_eq!(split_windows(r#"cl "" """#), ["cl", "", ""]);
+        assert_eq!(split_windows(r"  cl   /c  "), ["cl", "/c"]);
+    }
+}
@@ -0,0 +1,330 @@
+use crate::cdb::{CdbEntry, CommandSyntax, DedupPolicy, MergePolicy, OutputFormat};
+use crate::rules::{deserialize_parsed_opt, deserialize_parsed_vec, Rules};
+use serde::de::IgnoredAny;
+use std::collections::BTreeMap;
+
+/// rules which only apply to some of the entries. An entry has to match all
+/// of the given patterns.
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub struct Section {
+    /// glob pattern matched against the `file` of an entry
+    #[serde(default, deserialize_with = "deserialize_parsed_opt")]
+    pub files: Option<glob::Pattern>,
+
+    /// regex searched in the `file` of an entry
+    #[serde(default, deserialize_with = "deserialize_parsed_opt")]
+    pub files_regex: Option<regex::Regex>,
+
+    /// glob pattern matched against the `directory` of an entry
+    #[serde(default, deserialize_with = "deserialize_parsed_opt")]
+    pub directories: Option<glob::Pattern>,
+
+    /// regex searched in the `directory` of an entry
+    #[serde(default, deserialize_with = "deserialize_parsed_opt")]
+    pub directories_regex: Option<regex::Regex>,
+
+    #[serde(flatten)]
+    pub rules: Rules,
+
+    /// keys which don't belong to any option, rejected by `Config::load`
+    #[serde(flatten)]
+    pub unknown_keys: BTreeMap<String, IgnoredAny>,
+}
+
+impl Section {
+    pub fn matches(&self, entry: &CdbEntry) -> bool {
+        self.files.as_ref().is_none_or(|p| p.matches(&entry.file))
+            && self
+                .files_regex
+                .as_ref()
+                .is_none_or(|re| re.is_match(&entry.file))
+            && self
+                .directories
+                .as_ref()
+                .is_none_or(|p| p.matches(&entry.directory))
+            && self
+                .directories_regex
+                .as_ref()
+                .is_none_or(|re| re.is_match(&entry.directory))
+    }
+}
+
+/// what to do with entries which can't be patched
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnError {
+    Fail,
+    Skip,
+    KeepOriginal,
+}
+
+impl std::str::FromStr for OnError {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            "keep-original" => Ok(Self::KeepOriginal),
+            _ => Err(anyhow::anyhow!("unsupported error policy: {}", s)),
+        }
+    }
+}
+
+// patch configuration, either from the command line or a file. The keys of
+// the file are named like the command line options. This isn't a doc comment
+// because clap would use it as the description of the application.
+#[derive(clap::Clap, serde::Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub toolchain: crate::toolchain::ToolchainOpts,
+
+    /// form of the patched entries: command, arguments or preserve
+    /// [default: preserve]
+    #[clap(long)]
+    pub output_format: Option<OutputFormat>,
+
+    /// how `command` strings are split and escaped: gnu, windows or auto
+    /// (windows for cl and clang-cl, gnu otherwise) [default: auto]
+    #[clap(long)]
+    pub command_syntax: Option<CommandSyntax>,
+
+    /// which entries are kept if several input databases contain the same
+    /// file: first, last or all [default: all]
+    #[clap(long)]
+    pub merge_policy: Option<MergePolicy>,
+
+    /// keeps only one of the entries compiling the same file within the same
+    /// directory: first, last, compile (the first one using `-c`) or
+    /// most-defines (the first one with the most `-D` options). The dropped
+    /// entries are logged.
+    #[clap(long)]
+    pub dedup: Option<DedupPolicy>,
+
+    /// keeps only entries whose file matches one of the glob patterns. The
+    /// patterns are matched against the `file` of an entry.
+    #[clap(long, multiple_occurrences = true, number_of_values = 1)]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub include_files: Vec<glob::Pattern>,
+
+    /// removes entries whose file matches a glob pattern, even if it is
+    /// matched by `--include-files`
+    #[clap(long, multiple_occurrences = true, number_of_values = 1)]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub exclude_files: Vec<glob::Pattern>,
+
+    /// removes entries whose compiler contains a match of the regex, e.g.
+    /// `-as$`
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub exclude_compiler: Vec<regex::Regex>,
+
+    /// removes entries which don't compile, i.e. don't use `-c` or `/c`
+    #[clap(long)]
+    pub require_compile: bool,
+
+    /// removes entries which only preprocess or write dependencies, i.e. use
+    /// `-E`, `-M`, `-MM`, `/E`, `/EP` or `/P`
+    #[clap(long)]
+    pub exclude_preprocess: bool,
+
+    /// what to do with entries which can't be patched: fail (don't write the
+    /// patched database), skip (remove them) or keep-original. Failures are
+    /// reported and result in a non-zero exit code in any case.
+    /// [default: fail]
+    #[clap(long)]
+    pub on_error: Option<OnError>,
+
+    /// map a file extension to a language, e.g. `ino=c++`. The language uses
+    /// the names supported by `-x`.
+    #[clap(long, multiple_occurrences = true, number_of_values = 1)]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub map_extension: Vec<crate::language::ExtensionMapping>,
+
+    /// replaces a path prefix, e.g. `/workspace=/home/me/project`. Applies to
+    /// the directory, file and output of every entry, the compiler and the
+    /// paths within compiler options. The first matching mapping wins.
+    #[clap(long, multiple_occurrences = true, number_of_values = 1)]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub remap_path: Vec<crate::paths::PathMapping>,
+
+    /// makes the file, output and all paths within compiler options absolute
+    /// using the directory of the entry. `.` and `..` are resolved without
+    /// looking at the filesystem.
+    #[clap(long)]
+    pub absolutize: bool,
+
+    /// makes all paths within DIR relative, so the database can be moved
+    /// together with DIR. The directory of an entry becomes relative to DIR,
+    /// all other paths relative to the directory of the entry. Paths outside
+    /// of DIR stay as they are.
+    #[clap(long, value_name = "DIR")]
+    pub relativize_to: Option<String>,
+
+    /// a step of the patch pipeline: remap-path, absolutize, use-compiler,
+    /// resolve-toolchain, infer-target, ccreplace, ccadd, ccdel or
+    /// relativize. The steps run in the given order and may be repeated,
+    /// every step needed by the other options has to be given.
+    /// [default: the order above]
+    #[clap(long, multiple_occurrences = true, number_of_values = 1)]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub step: Vec<crate::pipeline::Step>,
+
+    #[clap(flatten)]
+    #[serde(flatten)]
+    pub rules: Rules,
+
+    #[clap(skip)]
+    #[serde(rename = "section")]
+    pub sections: Vec<Section>,
+
+    /// keys which don't belong to any option, rejected by `load`. This has
+    /// to be the last flattened field to only get the remaining keys.
+    #[clap(skip)]
+    #[serde(flatten)]
+    pub unknown_keys: BTreeMap<String, IgnoredAny>,
+}
+
+impl Config {
+    /// loads a config file. Files ending with `.json` are parsed as JSON,
+    /// everything else as TOML. Unknown keys are an error, so typos don't go
+    /// unnoticed.
+    pub fn load(path: &str) -> Result<Self, anyhow::Error> {
+        let data = std::fs::read_to_string(path)?;
+        let config: Self = if path.ends_with(".json") {
+            serde_json::from_str(&data)?
+        } else {
+            toml::from_str(&data)?
+        };
+
+        let unknown: Vec<_> = config
+            .unknown_keys
+            .keys()
+            .cloned()
+            .chain(config.sections.iter().flat_map(|section| {
+                section
+                    .unknown_keys
+                    .keys()
+                    .map(|key| format!("section.{}", key))
+            }))
+            .collect();
+        if !unknown.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{}: unknown keys: {}",
+                path,
+                unknown.join(", ")
+            ));
+        }
+
+        Ok(config)
+    }
+
+    /// combines two configurations, the options of `other` take precedence
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            toolchain: self.toolchain.merge(other.toolchain),
+            output_format: other.output_format.or(self.output_format),
+            command_syntax: other.command_syntax.or(self.command_syntax),
+            merge_policy: other.merge_policy.or(self.merge_policy),
+            include_files: self
+                .include_files
+                .into_iter()
+                .chain(other.include_files)
+                .collect(),
+            exclude_files: self
+                .exclude_files
+                .into_iter()
+                .chain(other.exclude_files)
+                .collect(),
+            exclude_compiler: self
+                .exclude_compiler
+                .into_iter()
+                .chain(other.exclude_compiler)
+                .collect(),
+            require_compile: self.require_compile || other.require_compile,
+            exclude_preprocess: self.exclude_preprocess || other.exclude_preprocess,
+            dedup: other.dedup.or(self.dedup),
+            on_error: other.on_error.or(self.on_error),
+            map_extension: self
+                .map_extension
+                .into_iter()
+                .chain(other.map_extension)
+                .collect(),
+            // the mappings of `other` come first since the first matching one
+            // wins
+            remap_path: other
+                .remap_path
+                .into_iter()
+                .chain(self.remap_path)
+                .collect(),
+            absolutize: self.absolutize || other.absolutize,
+            relativize_to: other.relativize_to.or(self.relativize_to),
+            // the order of the steps can't be combined
+            step: if other.step.is_empty() {
+                self.step
+            } else {
+                other.step
+            },
+            rules: self.rules.merge(other.rules),
+            sections: self.sections.into_iter().chain(other.sections).collect(),
+            unknown_keys: self
+                .unknown_keys
+                .into_iter()
+                .chain(other.unknown_keys)
+                .collect(),
+        }
+    }
+
+    /// returns true if the entry passes `--include-files` and the other
+    /// filters. Entries whose command can't be split are kept, so they get
+    /// reported by the pipeline.
+    pub fn selects(&self, entry: &CdbEntry) -> bool {
+        if !self.include_files.is_empty()
+            && !self.include_files.iter().any(|p| p.matches(&entry.file))
+        {
+            return false;
+        }
+        if self.exclude_files.iter().any(|p| p.matches(&entry.file)) {
+            return false;
+        }
+
+        let args = match entry.args(self.command_syntax.unwrap_or(CommandSyntax::Auto)) {
+            Ok(args) if !args.is_empty() => args,
+            _ => return true,
+        };
+        let command = crate::options::Command::parse(&args);
+
+        !(self
+            .exclude_compiler
+            .iter()
+            .any(|re| re.is_match(command.compiler))
+            || (self.require_compile && !command.compiles())
+            || (self.exclude_preprocess && command.preprocesses_only()))
+    }
+
+    /// returns the global rules followed by the ones of all sections
+    pub fn all_rules(&self) -> impl Iterator<Item = &Rules> {
+        std::iter::once(&self.rules).chain(self.sections.iter().map(|section| &section.rules))
+    }
+
+    /// returns the global rules followed by the ones of all sections matching
+    /// the entry
+    pub fn rules_for(&self, entry: &CdbEntry) -> Vec<&Rules> {
+        std::iter::once(&self.rules)
+            .chain(
+                self.sections
+                    .iter()
+                    .filter(|section| section.matches(entry))
+                    .map(|section| &section.rules),
+            )
+            .collect()
+    }
+}
@@ -0,0 +1,104 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    C,
+    Cxx,
+    ObjC,
+    ObjCxx,
+    /// assembler without preprocessing
+    Asm,
+    /// assembler with preprocessing
+    AsmCpp,
+    Cuda,
+}
+
+impl std::str::FromStr for Language {
+    type Err = anyhow::Error;
+
+    /// parses the language names used by `-x`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "c" | "c-header" | "cpp-output" => Ok(Self::C),
+            "c++" | "c++-header" | "c++-cpp-output" | "c++-module" => Ok(Self::Cxx),
+            "objective-c" | "objective-c-header" | "objc-cpp-output" => Ok(Self::ObjC),
+            "objective-c++" | "objective-c++-header" | "objc++-cpp-output" => Ok(Self::ObjCxx),
+            "assembler" => Ok(Self::Asm),
+            "assembler-with-cpp" => Ok(Self::AsmCpp),
+            "cuda" => Ok(Self::Cuda),
+            _ => Err(anyhow::anyhow!("unsupported language: {}", s)),
+        }
+    }
+}
+
+impl Language {
+    /// the name used by `-x`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::C => "c",
+            Self::Cxx => "c++",
+            Self::ObjC => "objective-c",
+            Self::ObjCxx => "objective-c++",
+            Self::Asm => "assembler",
+            Self::AsmCpp => "assembler-with-cpp",
+            Self::Cuda => "cuda",
+        }
+    }
+
+    /// detects the language the same way gcc does. The extension is case
+    /// sensitive, e.g. `.C` is C++ while `.c` is C.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "c" => Some(Self::C),
+            "cc" | "cp" | "cxx" | "cpp" | "CPP" | "c++" | "C" | "ixx" | "cppm" | "hh" | "H"
+            | "hp" | "hxx" | "hpp" | "HPP" | "h++" | "tcc" => Some(Self::Cxx),
+            "m" => Some(Self::ObjC),
+            "mm" | "M" => Some(Self::ObjCxx),
+            "s" => Some(Self::Asm),
+            "S" | "sx" => Some(Self::AsmCpp),
+            "cu" => Some(Self::Cuda),
+            _ => None,
+        }
+    }
+}
+
+/// maps a file extension to a language, e.g. `ino=c++`
+#[derive(Debug)]
+pub struct ExtensionMapping {
+    extension: String,
+    language: Language,
+}
+
+impl std::str::FromStr for ExtensionMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (extension, language) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("missing `=` in extension mapping: {}", s))?;
+
+        Ok(Self {
+            extension: extension.trim_start_matches('.').to_string(),
+            language: language.parse()?,
+        })
+    }
+}
+
+/// detects the language of an entry. An explicit `-x` takes precedence over
+/// the extension of the file. User defined mappings take precedence over the
+/// builtin ones.
+pub fn detect_language(
+    file: &str,
+    command: &crate::options::Command,
+    mappings: &[ExtensionMapping],
+) -> Option<Language> {
+    if let Some(language) = command.language() {
+        return Some(language);
+    }
+
+    let ext = std::path::Path::new(file).extension()?.to_str()?;
+    mappings
+        .iter()
+        .rev()
+        .find(|mapping| mapping.extension == ext)
+        .map(|mapping| mapping.language)
+        .or_else(|| Language::from_extension(ext))
+}
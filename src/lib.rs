@@ -0,0 +1,9 @@
+pub mod cache;
+pub mod cdb;
+pub mod config;
+pub mod language;
+pub mod options;
+pub mod paths;
+pub mod pipeline;
+pub mod rules;
+pub mod toolchain;
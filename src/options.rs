@@ -0,0 +1,729 @@
+use crate::language;
+
+/// the command line syntax of a compiler driver
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Driver {
+    /// gcc, clang and compatible drivers
+    Gcc,
+    /// `cl.exe` and `clang-cl`
+    Cl,
+}
+
+impl Driver {
+    /// detects the driver using `--driver-mode=` or the name of the compiler
+    pub fn detect(command: &[String]) -> Self {
+        let mode = command[1..]
+            .iter()
+            .rev()
+            .find_map(|arg| arg.strip_prefix("--driver-mode="));
+        if let Some(mode) = mode {
+            return if mode == "cl" { Self::Cl } else { Self::Gcc };
+        }
+
+        // the path might use either separator, independent of the host
+        let name = command[0].rsplit(['/', '\\']).next().unwrap();
+        let name = name.to_ascii_lowercase();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        if name == "cl" || name.starts_with("clang-cl") {
+            Self::Cl
+        } else {
+            Self::Gcc
+        }
+    }
+}
+
+/// how an option takes its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    /// no value, has to match exactly
+    Flag,
+    /// value is part of the same argument, e.g. `-std=c11` or `-O2`
+    Joined,
+    /// value is the next argument, e.g. `-Xclang -foo`
+    Separate,
+    /// either of the two, e.g. `-Ifoo` and `-I foo`
+    JoinedOrSeparate,
+    /// all remaining arguments belong to the option, e.g. `/link`
+    Remaining,
+}
+
+/// how an option influences toolchain probing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// doesn't change the toolchain includes or defines
+    Ignore,
+    /// changes the toolchain includes and possibly the defines
+    Toolchain,
+    /// changes the toolchain defines only
+    Defines,
+}
+
+/// the kind of include search path added by an option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IncludeKind {
+    /// `-I`, `/I`
+    User,
+    /// `-iquote`
+    Quote,
+    /// `-isystem`, `/imsvc`, `/external:I`
+    System,
+    /// `-idirafter`
+    After,
+    /// `-F`
+    Framework,
+}
+
+/// what an argument means
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// a source file or another positional argument
+    Input,
+    /// the output file, e.g. `-o` or `/Fo`
+    Output,
+    /// an include search path
+    Include(IncludeKind),
+    /// `-D`, the value is `NAME` or `NAME=VALUE`
+    Define,
+    /// `-U`
+    Undefine,
+    /// `-x` or `/TC`/`/TP`
+    Language,
+    /// the language standard, e.g. `-std=` or `/std:`
+    Std,
+    /// selects the target or its features, e.g. `--target=` or `-mcpu=`
+    Target,
+    /// a known option without a more specific kind
+    Other,
+    /// an option not contained in the tables
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct OptionSpec {
+    pub name: &'static str,
+    pub arity: Arity,
+    pub kind: ArgKind,
+    pub probe: Probe,
+    /// the value is a path
+    pub path: bool,
+}
+
+impl OptionSpec {
+    const fn new(name: &'static str, arity: Arity) -> Self {
+        Self {
+            name,
+            arity,
+            kind: ArgKind::Other,
+            probe: Probe::Ignore,
+            path: false,
+        }
+    }
+
+    const fn kind(mut self, kind: ArgKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
+    const fn probe(mut self, probe: Probe) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    const fn path(mut self) -> Self {
+        self.path = true;
+        self
+    }
+}
+
+const fn flag(name: &'static str) -> OptionSpec {
+    OptionSpec::new(name, Arity::Flag)
+}
+
+const fn joined(name: &'static str) -> OptionSpec {
+    OptionSpec::new(name, Arity::Joined)
+}
+
+const fn separate(name: &'static str) -> OptionSpec {
+    OptionSpec::new(name, Arity::Separate)
+}
+
+const fn either(name: &'static str) -> OptionSpec {
+    OptionSpec::new(name, Arity::JoinedOrSeparate)
+}
+
+use ArgKind::{Define, Include, Input, Output, Std, Target, Undefine, Unknown};
+use Probe::{Defines, Ignore, Toolchain};
+
+/// GCC and clang options. Options not listed here are treated as flags which
+/// are relevant for probing.
+pub static GCC_OPTIONS: &[OptionSpec] = &[
+    // actions and outputs
+    flag("-c"),
+    flag("-E"),
+    flag("-S"),
+    either("-o").kind(Output).path(),
+    either("-x").kind(ArgKind::Language),
+    flag("-v"),
+    flag("-pipe"),
+    flag("-save-temps"),
+    joined("-save-temps="),
+    separate("-dumpbase"),
+    separate("-dumpdir").path(),
+    separate("-auxbase"),
+    separate("-main-file-name"),
+    separate("-include-pch").path(),
+    // dependency files
+    flag("-M"),
+    flag("-MM"),
+    flag("-MD"),
+    flag("-MMD"),
+    flag("-MG"),
+    flag("-MP"),
+    either("-MF").path(),
+    either("-MT"),
+    either("-MQ"),
+    either("-MJ").path(),
+    // user includes and defines, the command keeps them anyway
+    either("-I").kind(Include(IncludeKind::User)).path(),
+    either("-iquote").kind(Include(IncludeKind::Quote)).path(),
+    either("-isystem").kind(Include(IncludeKind::System)).path(),
+    either("-idirafter")
+        .kind(Include(IncludeKind::After))
+        .path(),
+    either("-F").kind(Include(IncludeKind::Framework)).path(),
+    either("-iprefix").path(),
+    either("-iwithprefix").path(),
+    either("-iwithprefixbefore").path(),
+    either("-include").path(),
+    either("-imacros").path(),
+    either("-D").kind(Define),
+    either("-U").kind(Undefine),
+    // toolchain selection and search paths
+    joined("--sysroot=").probe(Toolchain).path(),
+    separate("--sysroot").probe(Toolchain).path(),
+    either("-isysroot").probe(Toolchain).path(),
+    either("-B").probe(Toolchain).path(),
+    joined("--specs=").probe(Toolchain).path(),
+    joined("-specs=").probe(Toolchain).path(),
+    joined("--gcc-toolchain=").probe(Toolchain).path(),
+    separate("-gcc-toolchain").probe(Toolchain).path(),
+    joined("--target=").kind(Target).probe(Toolchain),
+    separate("-target").kind(Target).probe(Toolchain),
+    separate("-arch").kind(Target).probe(Toolchain),
+    joined("--driver-mode=").probe(Toolchain),
+    flag("-nostdinc").probe(Toolchain),
+    flag("-nostdinc++").probe(Toolchain),
+    flag("-nostdlibinc").probe(Toolchain),
+    flag("-nobuiltininc").probe(Toolchain),
+    joined("-stdlib=").probe(Toolchain),
+    joined("-std=").kind(Std).probe(Toolchain),
+    flag("-ansi").kind(Std).probe(Toolchain),
+    joined("-m").kind(Target).probe(Toolchain),
+    separate("-Xclang").probe(Toolchain),
+    // code generation, only changes the defines
+    joined("-O").probe(Defines),
+    joined("-f").probe(Defines),
+    flag("-pthread").probe(Defines),
+    joined("-fmacro-prefix-map="),
+    joined("-ffile-prefix-map="),
+    joined("-fdebug-prefix-map="),
+    // diagnostics, debug info and other tools
+    joined("-W"),
+    joined("-Wp,"),
+    joined("-Wa,"),
+    joined("-Wl,"),
+    flag("-w"),
+    flag("-pedantic"),
+    flag("-pedantic-errors"),
+    joined("-g"),
+    separate("--param"),
+    joined("--param="),
+    separate("-Xpreprocessor"),
+    separate("-Xassembler"),
+    separate("-Xlinker"),
+    // linking
+    either("-L").path(),
+    either("-l"),
+    either("-T").path(),
+    separate("-z"),
+];
+
+/// clang-cl and MSVC options. Both `/` and `-` can be used as prefix, the
+/// names are case sensitive. Options not listed here are looked up in
+/// `GCC_OPTIONS` since clang-cl accepts some of them, e.g. `--target=`.
+pub static CL_OPTIONS: &[OptionSpec] = &[
+    // actions and outputs
+    flag("/c"),
+    flag("/E"),
+    flag("/EP"),
+    flag("/P"),
+    // the value may also follow a `:`, optionally as a separate argument,
+    // e.g. `/Fo:foo.obj` and `/Fo: foo.obj`
+    joined("/Fo").kind(Output).path(),
+    either("/Fo:").kind(Output).path(),
+    joined("/Fe").path(),
+    either("/Fe:").path(),
+    joined("/Fa").path(),
+    either("/Fa:").path(),
+    joined("/Fd").path(),
+    either("/Fd:").path(),
+    joined("/Fi").path(),
+    either("/Fi:").path(),
+    joined("/Fp").path(),
+    either("/Fp:").path(),
+    joined("/FR").path(),
+    either("/FR:").path(),
+    joined("/Fr").path(),
+    either("/Fr:").path(),
+    either("/FI").path(),
+    joined("/Yc").path(),
+    joined("/Yu").path(),
+    flag("/TC").kind(ArgKind::Language),
+    flag("/TP").kind(ArgKind::Language),
+    either("/Tc").kind(Input).path(),
+    either("/Tp").kind(Input).path(),
+    flag("/nologo"),
+    flag("/showIncludes"),
+    joined("/showIncludes:"),
+    // includes and defines
+    either("/I").kind(Include(IncludeKind::User)).path(),
+    either("/external:I")
+        .kind(Include(IncludeKind::System))
+        .path(),
+    joined("/external:W"),
+    either("/imsvc").kind(Include(IncludeKind::System)).path(),
+    either("/D").kind(Define),
+    either("/U").kind(Undefine),
+    flag("/u").probe(Defines),
+    flag("/X").probe(Toolchain),
+    // toolchain selection
+    joined("/std:").kind(Std).probe(Toolchain),
+    joined("/arch:").kind(Target).probe(Toolchain),
+    either("/winsysroot").probe(Toolchain).path(),
+    either("/vctoolsdir").probe(Toolchain).path(),
+    either("/winsdkdir").probe(Toolchain).path(),
+    joined("/winsdkversion").probe(Toolchain),
+    joined("/clang:").probe(Toolchain),
+    // code generation, only changes the defines
+    joined("/O").probe(Defines),
+    joined("/M").probe(Defines),
+    joined("/EH").probe(Defines),
+    joined("/G").probe(Defines),
+    joined("/Zc:").probe(Defines),
+    flag("/J").probe(Defines),
+    joined("/fp:").probe(Defines),
+    // diagnostics, debug info and linking
+    joined("/W"),
+    joined("/w"),
+    joined("/Z"),
+    joined("/analyze"),
+    joined("/diagnostics:"),
+    joined("/source-charset:"),
+    joined("/execution-charset:"),
+    flag("/utf-8"),
+    OptionSpec::new("/link", Arity::Remaining),
+];
+
+/// a single option or positional argument of a command
+#[derive(Debug, Clone)]
+pub struct ParsedArg<'a> {
+    /// `None` for positional arguments and unknown options
+    pub spec: Option<&'static OptionSpec>,
+    pub kind: ArgKind,
+    /// index of the first raw argument within the command
+    pub index: usize,
+    /// the raw arguments, two for separate values
+    pub raw: &'a [String],
+    /// the value of the option, the argument itself for inputs
+    pub value: Option<&'a str>,
+}
+
+impl<'a> ParsedArg<'a> {
+    /// how this argument influences toolchain probing. Unknown options are
+    /// assumed to be relevant.
+    pub fn probe(&self) -> Probe {
+        match (self.spec, self.kind) {
+            (Some(spec), _) => spec.probe,
+            (None, Unknown) => Toolchain,
+            (None, _) => Ignore,
+        }
+    }
+
+    /// returns the path value of the argument, if any
+    pub fn path(&self) -> Option<&'a str> {
+        if self.kind == Input || self.spec.is_some_and(|spec| spec.path) {
+            self.value
+        } else {
+            None
+        }
+    }
+
+    /// returns true if this is the given option
+    pub fn is(&self, name: &str) -> bool {
+        self.spec.is_some_and(|spec| spec.name == name)
+    }
+}
+
+/// finds the spec for an argument. The longest matching name wins, so e.g.
+/// `-isystem` beats `-I` and `-fmacro-prefix-map=` beats `-f`.
+/// Returns the spec and whether the value is joined.
+fn lookup(table: &'static [OptionSpec], arg: &str) -> Option<(&'static OptionSpec, bool)> {
+    table
+        .iter()
+        .filter_map(|spec| {
+            let rest = arg.strip_prefix(spec.name)?;
+            match (spec.arity, rest.is_empty()) {
+                (Arity::Joined, _) | (Arity::JoinedOrSeparate, false) => Some((spec, true)),
+                (_, true) => Some((spec, false)),
+                _ => None,
+            }
+        })
+        .max_by_key(|(spec, _)| spec.name.len())
+}
+
+/// finds the spec for an argument of the given driver
+fn lookup_driver(driver: Driver, arg: &str) -> Option<(&'static OptionSpec, bool)> {
+    match driver {
+        Driver::Gcc => lookup(GCC_OPTIONS, arg),
+        Driver::Cl => {
+            // the names in the table use `/`, both have the same length
+            let slashed = match arg.strip_prefix('-') {
+                Some(rest) if !rest.starts_with('-') => format!("/{rest}"),
+                _ => arg.to_string(),
+            };
+            lookup(CL_OPTIONS, &slashed).or_else(|| lookup(GCC_OPTIONS, arg))
+        }
+    }
+}
+
+/// a compiler command split into typed arguments
+#[derive(Debug, Clone)]
+pub struct Command<'a> {
+    pub driver: Driver,
+    pub compiler: &'a str,
+    /// the arguments following the compiler
+    pub args: Vec<ParsedArg<'a>>,
+}
+
+impl<'a> Command<'a> {
+    pub fn parse(command: &'a [String]) -> Self {
+        let driver = Driver::detect(command);
+        let mut args = Vec::with_capacity(command.len());
+        let mut i = 1;
+
+        while i < command.len() {
+            let arg = &command[i];
+
+            // clang-cl treats everything after `--` as input
+            if driver == Driver::Cl && arg == "--" {
+                args.extend((i + 1..command.len()).map(|index| ParsedArg {
+                    spec: None,
+                    kind: Input,
+                    index,
+                    raw: &command[index..=index],
+                    value: Some(command[index].as_str()),
+                }));
+                break;
+            }
+
+            let (spec, raw, value) = match lookup_driver(driver, arg) {
+                Some((spec, true)) => (Some(spec), &command[i..=i], Some(&arg[spec.name.len()..])),
+                Some((spec, false)) => match spec.arity {
+                    Arity::Separate | Arity::JoinedOrSeparate if i + 1 < command.len() => (
+                        Some(spec),
+                        &command[i..i + 2],
+                        Some(command[i + 1].as_str()),
+                    ),
+                    Arity::Remaining => (Some(spec), &command[i..], None),
+                    _ => (Some(spec), &command[i..=i], None),
+                },
+                // arguments starting with `/` which aren't options are
+                // absolute paths, also for clang-cl
+                None if !arg.starts_with('-') => (None, &command[i..=i], Some(arg.as_str())),
+                None => (None, &command[i..=i], None),
+            };
+            let kind = match spec {
+                Some(spec) => spec.kind,
+                None if value.is_some() => Input,
+                None => Unknown,
+            };
+
+            args.push(ParsedArg {
+                spec,
+                kind,
+                index: i,
+                raw,
+                value,
+            });
+            i += raw.len();
+        }
+
+        Self {
+            driver,
+            compiler: &command[0],
+            args,
+        }
+    }
+
+    fn values(&self, kind: ArgKind) -> impl Iterator<Item = &'a str> + '_ {
+        self.args
+            .iter()
+            .filter(move |arg| arg.kind == kind)
+            .filter_map(|arg| arg.value)
+    }
+
+    /// the source files and other positional arguments
+    pub fn inputs(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.values(Input)
+    }
+
+    /// the output file. The last one wins like for the compiler.
+    pub fn output(&self) -> Option<&'a str> {
+        self.values(Output).last()
+    }
+
+    /// the include search paths of the given kind in command line order
+    pub fn includes(&self, kind: IncludeKind) -> impl Iterator<Item = &'a str> + '_ {
+        self.values(Include(kind))
+    }
+
+    /// the defines in the form `NAME` or `NAME=VALUE`
+    pub fn defines(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.values(Define)
+    }
+
+    /// the names of undefined macros
+    pub fn undefines(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.values(Undefine)
+    }
+
+    /// returns true if the command compiles without linking, i.e. it has
+    /// `-c` or `/c`
+    pub fn compiles(&self) -> bool {
+        self.args.iter().any(|arg| arg.is("-c") || arg.is("/c"))
+    }
+
+    /// returns true if the command only preprocesses or only writes
+    /// dependencies, e.g. because of `-E`, `-M` or `/P`
+    pub fn preprocesses_only(&self) -> bool {
+        const NAMES: &[&str] = &["-E", "-M", "-MM", "/E", "/EP", "/P"];
+        self.args
+            .iter()
+            .any(|arg| NAMES.iter().any(|name| arg.is(name)))
+    }
+
+    /// the language set explicitly using `-x`, `/TC` or `/TP`. Only the last
+    /// one counts since it applies to the source files following it.
+    pub fn language(&self) -> Option<language::Language> {
+        let mut ret = None;
+
+        for arg in self.args.iter().filter(|arg| arg.kind == ArgKind::Language) {
+            ret = if arg.is("/TC") {
+                Some(language::Language::C)
+            } else if arg.is("/TP") {
+                Some(language::Language::Cxx)
+            } else {
+                // `-x none` restores detection by extension
+                arg.value.and_then(|name| name.parse().ok())
+            };
+        }
+
+        ret
+    }
+
+    /// the language standard, e.g. `c++17`
+    pub fn std(&self) -> Option<&'a str> {
+        self.args
+            .iter()
+            .filter(|arg| arg.kind == Std)
+            .map(|arg| arg.value.unwrap_or(&arg.raw[0]))
+            .next_back()
+    }
+
+    /// the options selecting the target or its features
+    pub fn target_flags(&self) -> impl Iterator<Item = &'a [String]> + '_ {
+        self.args
+            .iter()
+            .filter(|arg| arg.kind == Target)
+            .map(|arg| arg.raw)
+    }
+
+    /// returns true if the target is set using `--target=` or `-target`
+    pub fn has_target(&self) -> bool {
+        self.args
+            .iter()
+            .any(|arg| arg.is("--target=") || arg.is("-target"))
+    }
+
+    /// the options which aren't contained in the tables
+    pub fn unknown(&self) -> impl Iterator<Item = &'a str> + '_ {
+        self.args
+            .iter()
+            .filter(|arg| arg.kind == Unknown)
+            .map(|arg| arg.raw[0].as_str())
+    }
+}
+
+/// rewrites the path values of all options and inputs of a command. `f`
+/// returns the new path or `None` to keep it. The compiler isn't touched.
+pub fn rewrite_paths<F>(command: &[String], f: F) -> Vec<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut ret = command.to_vec();
+
+    for arg in Command::parse(command).args {
+        let new = match arg.path().and_then(&f) {
+            Some(new) => new,
+            None => continue,
+        };
+        let last = arg.index + arg.raw.len() - 1;
+        ret[last] = match (arg.spec, arg.raw.len()) {
+            // joined value, keep the option as it was written
+            (Some(_), 1) => {
+                let raw = &arg.raw[0];
+                format!("{}{new}", &raw[..raw.len() - arg.value.unwrap().len()])
+            }
+            _ => new,
+        };
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::Language;
+
+    fn command(args: &str) -> Vec<String> {
+        args.split(' ').map(|arg| arg.to_string()).collect()
+    }
+
+    /// the names of the parsed options and their values
+    fn parsed(command: &[String]) -> Vec<(Option<&str>, Option<&str>)> {
+        Command::parse(command)
+            .args
+            .iter()
+            .map(|arg| (arg.spec.map(|spec| spec.name), arg.value))
+            .collect()
+    }
+
+    #[test]
+    fn joined_and_separate_values() {
+        let cmd = command("gcc -Ifoo -I bar -DX=1 -D Y -o a.o -c a.c");
+        assert_eq!(
+            parsed(&cmd),
+            [
+                (Some("-I"), Some("foo")),
+                (Some("-I"), Some("bar")),
+                (Some("-D"), Some("X=1")),
+                (Some("-D"), Some("Y")),
+                (Some("-o"), Some("a.o")),
+                (Some("-c"), None),
+                (None, Some("a.c")),
+            ]
+        );
+
+        let parsed = Command::parse(&cmd);
+        assert_eq!(parsed.args[1].index, 2);
+        assert_eq!(parsed.args[1].raw, ["-I", "bar"]);
+        assert_eq!(
+            parsed.includes(IncludeKind::User).collect::<Vec<_>>(),
+            ["foo", "bar"]
+        );
+        assert_eq!(parsed.output(), Some("a.o"));
+        assert_eq!(parsed.inputs().collect::<Vec<_>>(), ["a.c"]);
+        assert!(parsed.compiles());
+    }
+
+    #[test]
+    fn longest_match() {
+        let cmd = command("gcc -isystem/sys -Iuser -include-pch x.pch -include a.h -includeb.h");
+        assert_eq!(
+            parsed(&cmd),
+            [
+                (Some("-isystem"), Some("/sys")),
+                (Some("-I"), Some("user")),
+                (Some("-include-pch"), Some("x.pch")),
+                (Some("-include"), Some("a.h")),
+                (Some("-include"), Some("b.h")),
+            ]
+        );
+        let parsed = Command::parse(&cmd);
+        assert_eq!(
+            parsed.includes(IncludeKind::System).collect::<Vec<_>>(),
+            ["/sys"]
+        );
+        assert_eq!(
+            parsed.includes(IncludeKind::User).collect::<Vec<_>>(),
+            ["user"]
+        );
+    }
+
+    #[test]
+    fn language() {
+        let cmd = command("gcc -x c++ a.c");
+        assert_eq!(Command::parse(&cmd).language(), Some(Language::Cxx));
+        let cmd = command("gcc -xc -x none a.c");
+        assert_eq!(Command::parse(&cmd).language(), None);
+        let cmd = command("clang-cl /TC a.c");
+        assert_eq!(Command::parse(&cmd).language(), Some(Language::C));
+        let cmd = command("clang-cl -TP a.c");
+        assert_eq!(Command::parse(&cmd).language(), Some(Language::Cxx));
+    }
+
+    #[test]
+    fn cl_output() {
+        for args in [
+            "clang-cl /c a.c /Foout.obj",
+            "clang-cl /c a.c /Fo:out.obj",
+            "clang-cl /c a.c /Fo: out.obj",
+            "clang-cl /c a.c -Fo:out.obj",
+        ]
+        .iter()
+        {
+            let cmd = command(args);
+            let parsed = Command::parse(&cmd);
+            assert_eq!(parsed.driver, Driver::Cl);
+            assert_eq!(parsed.output(), Some("out.obj"), "{}", args);
+            assert_eq!(parsed.inputs().collect::<Vec<_>>(), ["a.c"], "{}", args);
+        }
+    }
+
+    #[test]
+    fn cl_inputs_and_link() {
+        let cmd = command("clang-cl /c /Ifoo -- /c.c -x.c");
+        let parsed = Command::parse(&cmd);
+        assert_eq!(parsed.inputs().collect::<Vec<_>>(), ["/c.c", "-x.c"]);
+        assert!(parsed.compiles());
+
+        let cmd = command("cl a.c /link /OUT:a.exe b.lib");
+        let parsed = Command::parse(&cmd);
+        assert_eq!(parsed.args.len(), 2);
+        assert_eq!(parsed.args[1].raw, ["/link", "/OUT:a.exe", "b.lib"]);
+        assert_eq!(parsed.inputs().collect::<Vec<_>>(), ["a.c"]);
+
+        let cmd = command("clang --driver-mode=cl /c a.c");
+        assert_eq!(Command::parse(&cmd).driver, Driver::Cl);
+    }
+
+    #[test]
+    fn rewrite_joined_paths() {
+        let prefix = |path: &str| Some(format!("/w/{}", path));
+
+        let cmd = command("gcc -Iinc -isystem sys -isystemsys2 -include-pch x.pch -c a.c -DX=y");
+        assert_eq!(
+            rewrite_paths(&cmd, prefix),
+            command(
+                "gcc -I/w/inc -isystem /w/sys -isystem/w/sys2 -include-pch /w/x.pch -c /w/a.c -DX=y"
+            )
+        );
+
+        let cmd = command("clang-cl /Iinc /Fo:obj /Fd: pdb -Fefoo.exe a.c");
+        assert_eq!(
+            rewrite_paths(&cmd, prefix),
+            command("clang-cl /I/w/inc /Fo:/w/obj /Fd: /w/pdb -Fe/w/foo.exe /w/a.c")
+        );
+    }
+}
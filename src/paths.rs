@@ -0,0 +1,227 @@
+/// replaces the prefix `from` of paths with `to`, e.g. `/workspace=/home/me/src`
+#[derive(Debug, Clone)]
+pub struct PathMapping {
+    from: String,
+    to: String,
+}
+
+impl std::str::FromStr for PathMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("missing `=` in path mapping: {}", s))?;
+        if from.is_empty() {
+            return Err(anyhow::anyhow!("empty prefix in path mapping: {}", s));
+        }
+
+        // a trailing separator doesn't change which paths match, except for
+        // the root
+        let trimmed = from.trim_end_matches(is_separator);
+        let from = if trimmed.is_empty() {
+            &from[..1]
+        } else {
+            trimmed
+        };
+
+        Ok(Self {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+fn is_separator(c: char) -> bool {
+    c == '/' || c == '\\'
+}
+
+impl PathMapping {
+    /// returns the remapped path if `from` is a prefix of it. The prefix has
+    /// to end at a path component boundary, so `/a` doesn't match `/ab`.
+    /// `to` and the rest of the path are joined using exactly one separator.
+    pub fn apply(&self, path: &str) -> Option<String> {
+        let rest = path.strip_prefix(&self.from)?;
+        let (separator, rest) = if self.from.ends_with(is_separator) {
+            (&self.from[self.from.len() - 1..], rest)
+        } else if rest.is_empty() {
+            return Some(self.to.clone());
+        } else if rest.starts_with(is_separator) {
+            rest.split_at(1)
+        } else {
+            return None;
+        };
+
+        if self.to.ends_with(is_separator) {
+            Some(format!("{}{}", self.to, rest))
+        } else {
+            Some(format!("{}{}{}", self.to, separator, rest))
+        }
+    }
+}
+
+/// remaps a path using the first matching mapping
+pub fn remap(mappings: &[PathMapping], path: &str) -> Option<String> {
+    mappings.iter().find_map(|mapping| mapping.apply(path))
+}
+
+/// returns true if the path starts with a drive letter, e.g. `C:\`
+fn has_drive(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && is_separator(bytes[2] as char)
+}
+
+/// returns true for absolute POSIX and Windows paths, independent of the
+/// host
+pub fn is_absolute(path: &str) -> bool {
+    path.starts_with(is_separator) || has_drive(path)
+}
+
+/// the separator used by a path, `\` for Windows paths
+pub fn separator(path: &str) -> &'static str {
+    if has_drive(path) || (path.contains('\\') && !path.contains('/')) {
+        "\\"
+    } else {
+        "/"
+    }
+}
+
+/// splits a path into its root and its components, ignoring `.`
+fn split(path: &str) -> (&str, Vec<&str>) {
+    let root = if has_drive(path) {
+        &path[..3]
+    } else if path.starts_with(is_separator) {
+        &path[..1]
+    } else {
+        ""
+    };
+    let components = path[root.len()..]
+        .split(is_separator)
+        .filter(|c| !c.is_empty() && *c != ".")
+        .collect();
+
+    (root, components)
+}
+
+/// removes `.` and resolves `..` without looking at the filesystem
+pub fn normalize(path: &str) -> String {
+    let (root, components) = split(path);
+    let mut ret: Vec<&str> = Vec::with_capacity(components.len());
+
+    for component in components {
+        if component != ".." {
+            ret.push(component);
+        } else if ret.last().is_some_and(|last| *last != "..") {
+            ret.pop();
+        } else if root.is_empty() {
+            // `..` of the root is the root itself
+            ret.push(component);
+        }
+    }
+
+    let ret = ret.join(separator(path));
+    if root.is_empty() && ret.is_empty() {
+        ".".to_string()
+    } else {
+        format!("{root}{ret}")
+    }
+}
+
+/// makes a path absolute using the absolute directory `base`
+pub fn absolutize(base: &str, path: &str) -> String {
+    if is_absolute(path) {
+        normalize(path)
+    } else {
+        normalize(&format!("{base}{}{path}", separator(base)))
+    }
+}
+
+/// returns true if the absolute path is `dir` or within it
+pub fn is_within(dir: &str, path: &str) -> bool {
+    let (dir_root, dir) = split(dir);
+    let (path_root, path) = split(path);
+    dir_root == path_root && path.starts_with(&dir)
+}
+
+/// returns `path` relative to `base`. Both have to be absolute and
+/// normalized. Returns `None` if they have different roots, e.g. drives.
+pub fn relative_to(base: &str, path: &str) -> Option<String> {
+    let (base_root, base) = split(base);
+    let (path_root, components) = split(path);
+    if base_root != path_root {
+        return None;
+    }
+
+    let common = base
+        .iter()
+        .zip(&components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let ret: Vec<_> = std::iter::repeat_n("..", base.len() - common)
+        .chain(components[common..].iter().copied())
+        .collect();
+
+    if ret.is_empty() {
+        Some(".".to_string())
+    } else {
+        Some(ret.join(separator(path)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_paths() {
+        assert_eq!(normalize("/a/./b/../c/"), "/a/c");
+        assert_eq!(normalize("/.."), "/");
+        assert_eq!(normalize("/a/../../b"), "/b");
+        assert_eq!(normalize("a/../.."), "..");
+        assert_eq!(normalize("../a/./.."), "..");
+        assert_eq!(normalize("a/.."), ".");
+        assert_eq!(normalize(r"C:\a\..\..\b"), r"C:\b");
+        assert_eq!(normalize(r"C:\a\.\b\"), r"C:\a\b");
+        assert_eq!(normalize(r"a\b\..\c"), r"a\c");
+    }
+
+    #[test]
+    fn absolutize_paths() {
+        assert_eq!(absolutize("/w/build", "../src/a.c"), "/w/src/a.c");
+        assert_eq!(absolutize("/w/build", "/usr/include"), "/usr/include");
+        assert_eq!(absolutize("/w", "../../.."), "/");
+        assert_eq!(absolutize(r"C:\w\build", r"..\src\a.c"), r"C:\w\src\a.c");
+        assert_eq!(absolutize(r"C:\w", r"D:\x\..\y"), r"D:\y");
+        assert!(is_absolute(r"C:\w") && is_absolute("/w") && is_absolute(r"\w"));
+        assert!(!is_absolute("C:w") && !is_absolute("w"));
+    }
+
+    #[test]
+    fn within() {
+        assert!(is_within("/a/b", "/a/b"));
+        assert!(is_within("/a/b", "/a/b/c"));
+        assert!(!is_within("/a/b", "/a/bc"));
+        assert!(!is_within("/a/b", "/a"));
+        assert!(is_within("/", "/a"));
+        assert!(is_within(r"C:\a", r"C:\a\b"));
+        assert!(!is_within(r"C:\a", r"D:\a\b"));
+        assert!(!is_within(r"C:\a", "/a/b"));
+    }
+
+    #[test]
+    fn relative() {
+        assert_eq!(relative_to("/a/b", "/a/b/c/d").as_deref(), Some("c/d"));
+        assert_eq!(relative_to("/a/b", "/a/b").as_deref(), Some("."));
+        assert_eq!(relative_to("/a/b", "/a/bc").as_deref(), Some("../bc"));
+        assert_eq!(relative_to("/a/b/c", "/x").as_deref(), Some("../../../x"));
+        assert_eq!(relative_to("/", "/a").as_deref(), Some("a"));
+        assert_eq!(
+            relative_to(r"C:\a\b", r"C:\a\c\d").as_deref(),
+            Some(r"..\c\d")
+        );
+        assert_eq!(relative_to(r"C:\a", r"D:\a"), None);
+    }
+}
@@ -0,0 +1,524 @@
+use crate::cdb::CdbEntry;
+use crate::config::Config;
+use crate::language::Language;
+use crate::options::Driver;
+use crate::rules::Rules;
+use crate::toolchain::{ToolchainInfoCache, ToolchainOpts};
+use rayon::prelude::*;
+
+/// the state of an entry while it passes through the pipeline
+#[derive(Debug)]
+pub struct Job<'a> {
+    pub entry: &'a CdbEntry,
+    /// the fields of the entry, written back after the last step
+    pub directory: String,
+    pub file: String,
+    pub output: Option<String>,
+    /// the command being patched, starting with the compiler
+    pub command: Vec<String>,
+    pub language: Option<Language>,
+    /// the rules which apply to the entry
+    pub rules: Vec<&'a Rules>,
+}
+
+impl Job<'_> {
+    /// the language used for probing. Assembler sources aren't preprocessed
+    /// and don't use includes.
+    pub fn probe_language(&self) -> Option<Language> {
+        self.language.filter(|&l| l != Language::Asm)
+    }
+}
+
+/// a step of the pipeline modifying the command of every entry
+pub trait EntryTransform: Sync {
+    /// gets called with all entries before `apply`, e.g. to run expensive
+    /// work once for every distinct configuration
+    fn prepare(&self, _jobs: &[&Job<'_>]) {}
+
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error>;
+}
+
+/// a step of the pipeline created from the options
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    RemapPath,
+    Absolutize,
+    UseCompiler,
+    ResolveToolchain,
+    InferTarget,
+    Ccreplace,
+    Ccadd,
+    Ccdel,
+    Relativize,
+}
+
+impl std::str::FromStr for Step {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::DEFAULT
+            .iter()
+            .copied()
+            .find(|step| step.name() == s)
+            .ok_or_else(|| anyhow::anyhow!("unsupported step: {}", s))
+    }
+}
+
+impl Step {
+    /// the order used if no steps are given. The compilers are overridden
+    /// first since the probes depend on them. `--target` gets added after
+    /// probing because gcc doesn't support it. Paths are made relative at
+    /// the end, so the added paths are covered as well.
+    pub const DEFAULT: &'static [Self] = &[
+        Self::RemapPath,
+        Self::Absolutize,
+        Self::UseCompiler,
+        Self::ResolveToolchain,
+        Self::InferTarget,
+        Self::Ccreplace,
+        Self::Ccadd,
+        Self::Ccdel,
+        Self::Relativize,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::RemapPath => "remap-path",
+            Self::Absolutize => "absolutize",
+            Self::UseCompiler => "use-compiler",
+            Self::ResolveToolchain => "resolve-toolchain",
+            Self::InferTarget => "infer-target",
+            Self::Ccreplace => "ccreplace",
+            Self::Ccadd => "ccadd",
+            Self::Ccdel => "ccdel",
+            Self::Relativize => "relativize",
+        }
+    }
+
+    /// returns true if the options of `config` need this step
+    fn is_used(self, config: &Config) -> bool {
+        let opts = &config.toolchain;
+        let mut rules = config.all_rules();
+
+        match self {
+            Self::RemapPath => !config.remap_path.is_empty(),
+            Self::Absolutize => config.absolutize,
+            Self::UseCompiler => rules.any(|r| r.has_compiler_override()),
+            Self::ResolveToolchain => {
+                opts.resolve_toolchain_includes || opts.resolve_toolchain_defines
+            }
+            Self::InferTarget => opts.infer_target,
+            Self::Ccreplace => rules.any(|r| !r.ccreplace.is_empty()),
+            Self::Ccadd => rules.any(|r| !r.ccadd.is_empty()),
+            Self::Ccdel => rules.any(|r| r.has_deletions()),
+            Self::Relativize => config.relativize_to.is_some(),
+        }
+    }
+}
+
+/// a sequence of transforms. Every step is applied to all entries before the
+/// next one starts, so its `prepare` sees the commands as they are at that
+/// point.
+pub struct Pipeline<'a> {
+    config: &'a Config,
+    steps: Vec<Box<dyn EntryTransform + 'a>>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// creates a pipeline without any steps
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            steps: Vec::new(),
+        }
+    }
+
+    /// creates the pipeline for the options of `config`. The steps are
+    /// taken from `--step` or the default order.
+    pub fn from_config(
+        config: &'a Config,
+        tic: &'a ToolchainInfoCache,
+    ) -> Result<Self, anyhow::Error> {
+        let opts = &config.toolchain;
+        let steps = if config.step.is_empty() {
+            Step::DEFAULT
+        } else {
+            &config.step
+        };
+        if let Some(step) = Step::DEFAULT
+            .iter()
+            .find(|step| step.is_used(config) && !steps.contains(step))
+        {
+            return Err(anyhow::anyhow!(
+                "the options need the step {} which isn't part of --step",
+                step.name()
+            ));
+        }
+
+        let header = opts.resolve_toolchain_defines
+            && opts.toolchain_defines_mode == Some(crate::toolchain::DefinesMode::Header);
+        if header
+            && !opts
+                .toolchain_defines_dir
+                .as_deref()
+                .is_some_and(crate::paths::is_absolute)
+        {
+            return Err(anyhow::anyhow!(
+                "--toolchain-defines-mode=header needs an absolute --toolchain-defines-dir, \
+                 see ToolchainOpts::init_defines_dir"
+            ));
+        }
+
+        let mut pipeline = Self::new(config);
+        for step in steps.iter().filter(|step| step.is_used(config)) {
+            match step {
+                Step::RemapPath => pipeline.push(RemapPaths {
+                    mappings: &config.remap_path,
+                }),
+                Step::Absolutize => pipeline.push(AbsolutizePaths),
+                Step::UseCompiler => pipeline.push(CompilerOverride),
+                Step::ResolveToolchain => pipeline.push(ResolveToolchain { tic, opts }),
+                Step::InferTarget => pipeline.push(InferTarget { tic, opts }),
+                Step::Ccreplace => pipeline.push(ReplaceArgs),
+                Step::Ccadd => pipeline.push(AddArgs),
+                Step::Ccdel => pipeline.push(DeleteArgs),
+                Step::Relativize => pipeline.push(RelativizePaths::new(
+                    config.relativize_to.as_deref().unwrap(),
+                )?),
+            };
+        }
+
+        Ok(pipeline)
+    }
+
+    pub fn push<T: EntryTransform + 'a>(&mut self, step: T) -> &mut Self {
+        self.steps.push(Box::new(step));
+        self
+    }
+
+    fn command_syntax(&self) -> crate::cdb::CommandSyntax {
+        self.config
+            .command_syntax
+            .unwrap_or(crate::cdb::CommandSyntax::Auto)
+    }
+
+    fn job<'e>(&self, entry: &'e CdbEntry) -> Result<Job<'e>, anyhow::Error>
+    where
+        'a: 'e,
+    {
+        let command = entry.args(self.command_syntax())?;
+        if command.is_empty() {
+            return Err(anyhow::anyhow!("empty command"));
+        }
+        let language = crate::language::detect_language(
+            &entry.file,
+            &crate::options::Command::parse(&command),
+            &self.config.map_extension,
+        );
+
+        Ok(Job {
+            entry,
+            directory: entry.directory.clone(),
+            file: entry.file.clone(),
+            output: entry.output.clone(),
+            command,
+            language,
+            rules: self.config.rules_for(entry),
+        })
+    }
+
+    /// patches all entries. Entries which fail keep their original command,
+    /// the error is returned at their index.
+    pub fn run(&self, cdb: &mut [CdbEntry]) -> Vec<Result<(), anyhow::Error>> {
+        let mut jobs: Vec<_> = cdb.par_iter().map(|entry| self.job(entry)).collect();
+
+        for step in &self.steps {
+            let ready: Vec<_> = jobs.iter().filter_map(|job| job.as_ref().ok()).collect();
+            step.prepare(&ready);
+
+            jobs.par_iter_mut().for_each(|job| {
+                let result = match job {
+                    Ok(job) => step.apply(job),
+                    Err(_) => return,
+                };
+                if let Err(e) = result {
+                    *job = Err(e);
+                }
+            });
+        }
+
+        let patched: Vec<_> = jobs
+            .into_iter()
+            .map(|job| job.map(|job| (job.command, job.directory, job.file, job.output)))
+            .collect();
+        let output_format = self
+            .config
+            .output_format
+            .unwrap_or(crate::cdb::OutputFormat::Preserve);
+
+        cdb.iter_mut()
+            .zip(patched)
+            .map(|(entry, patched)| {
+                let (command, directory, file, output) = patched?;
+                entry.set_args(command, output_format, self.command_syntax());
+                entry.directory = directory;
+                entry.file = file;
+                entry.output = output;
+                Ok(())
+            })
+            .collect()
+    }
+}
+
+/// applies `--remap-path` to the paths of the entry and its command
+pub struct RemapPaths<'a> {
+    pub mappings: &'a [crate::paths::PathMapping],
+}
+
+impl EntryTransform for RemapPaths<'_> {
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        let remap = |path: &str| crate::paths::remap(self.mappings, path);
+
+        let mut command = crate::options::rewrite_paths(&job.command, remap);
+        if let Some(compiler) = remap(&command[0]) {
+            command[0] = compiler;
+        }
+        job.command = command;
+
+        for path in std::iter::once(&mut job.directory)
+            .chain(std::iter::once(&mut job.file))
+            .chain(job.output.as_mut())
+        {
+            if let Some(new) = remap(path) {
+                *path = new;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// applies `--absolutize`
+pub struct AbsolutizePaths;
+
+impl EntryTransform for AbsolutizePaths {
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        use crate::paths::{absolutize, is_absolute};
+
+        if !is_absolute(&job.directory) {
+            return Err(anyhow::anyhow!("directory isn't absolute"));
+        }
+        let directory = &job.directory;
+
+        let mut command =
+            crate::options::rewrite_paths(&job.command, |path| Some(absolutize(directory, path)));
+        // compilers without a directory are searched in `PATH`
+        if command[0].contains(['/', '\\']) {
+            command[0] = absolutize(directory, &command[0]);
+        }
+        job.command = command;
+
+        job.file = absolutize(directory, &job.file);
+        if let Some(output) = &mut job.output {
+            *output = absolutize(directory, output);
+        }
+
+        Ok(())
+    }
+}
+
+/// applies `--relativize-to`
+pub struct RelativizePaths {
+    /// absolute and normalized
+    base: String,
+}
+
+impl RelativizePaths {
+    /// `base` is relative to the current directory
+    pub fn new(base: &str) -> Result<Self, anyhow::Error> {
+        let cwd = std::env::current_dir()?;
+        Ok(Self {
+            base: crate::paths::absolutize(&cwd.to_string_lossy(), base),
+        })
+    }
+}
+
+impl EntryTransform for RelativizePaths {
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        use crate::paths::{absolutize, is_absolute, is_within, normalize, relative_to, separator};
+
+        // paths are relative to the directory, so it has to be within the
+        // base as well
+        let directory = normalize(&job.directory);
+        if !is_absolute(&directory) || !is_within(&self.base, &directory) {
+            return Ok(());
+        }
+        let relativize = |path: &str| {
+            let path = absolutize(&directory, path);
+            if is_within(&self.base, &path) {
+                relative_to(&directory, &path)
+            } else {
+                None
+            }
+        };
+
+        let mut command = crate::options::rewrite_paths(&job.command, relativize);
+        if command[0].contains(['/', '\\']) {
+            if let Some(compiler) = relativize(&command[0]) {
+                // a compiler without a directory would be searched in `PATH`
+                command[0] = if compiler.contains(['/', '\\']) {
+                    compiler
+                } else {
+                    format!(".{}{compiler}", separator(&directory))
+                };
+            }
+        }
+        job.command = command;
+
+        if let Some(file) = relativize(&job.file) {
+            job.file = file;
+        }
+        if let Some(output) = job.output.as_deref().and_then(relativize) {
+            job.output = Some(output);
+        }
+        job.directory = relative_to(&self.base, &directory).unwrap();
+
+        Ok(())
+    }
+}
+
+/// replaces the compiler using `--use-cc` and friends
+pub struct CompilerOverride;
+
+impl EntryTransform for CompilerOverride {
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        let language = match job.language {
+            Some(language) => language,
+            None => return Ok(()),
+        };
+
+        // the last matching override wins
+        let compiler = job
+            .rules
+            .iter()
+            .rev()
+            .find_map(|r| r.compiler_override(language));
+        if let Some(s) = compiler {
+            job.command[0] = s.to_string();
+        }
+
+        Ok(())
+    }
+}
+
+/// adds the toolchain includes and defines
+pub struct ResolveToolchain<'a> {
+    pub tic: &'a ToolchainInfoCache,
+    pub opts: &'a ToolchainOpts,
+}
+
+impl EntryTransform for ResolveToolchain<'_> {
+    /// runs every distinct probe exactly once
+    fn prepare(&self, jobs: &[&Job<'_>]) {
+        let probes: std::collections::HashSet<_> = jobs
+            .par_iter()
+            .filter(|job| !crate::toolchain::is_nvcc(&job.command[0]))
+            .filter_map(|job| {
+                Some(ToolchainInfoCache::probe_key(
+                    job.probe_language()?,
+                    self.opts,
+                    &job.command,
+                    &job.directory,
+                ))
+            })
+            .collect();
+        self.tic.probe_all(self.opts, &probes);
+    }
+
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        let language = match job.probe_language() {
+            Some(language) => language,
+            None => return Ok(()),
+        };
+        if crate::toolchain::is_nvcc(&job.command[0]) {
+            return Err(anyhow::anyhow!(
+                "nvcc can't be probed, use --use-cuda to select another compiler, e.g. clang"
+            ));
+        }
+
+        self.tic
+            .add_toolchain_info(language, self.opts, &job.directory, &mut job.command)?;
+
+        // `/X` is the closest clang-cl equivalent of `-nostdinc`, the builtin
+        // headers of clang are part of the probed includes anyway
+        let nostdinc = match Driver::detect(&job.command) {
+            Driver::Gcc => "-nostdinc",
+            Driver::Cl => "/X",
+        };
+        if self.opts.resolve_toolchain_includes && !job.command.iter().any(|s| s == nostdinc) {
+            job.command.push(nostdinc.to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// adds `--target` for cross compilers
+pub struct InferTarget<'a> {
+    pub tic: &'a ToolchainInfoCache,
+    pub opts: &'a ToolchainOpts,
+}
+
+impl EntryTransform for InferTarget<'_> {
+    fn prepare(&self, jobs: &[&Job<'_>]) {
+        let keys: std::collections::HashSet<_> = jobs
+            .iter()
+            .filter(|job| !ToolchainInfoCache::has_target(&job.command))
+            .map(|job| ToolchainInfoCache::target_key(&job.command, &job.directory))
+            .collect();
+        self.tic.probe_targets(self.opts, &keys);
+    }
+
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        self.tic
+            .add_target(self.opts, &job.directory, &mut job.command)
+    }
+}
+
+/// applies `--ccreplace`
+pub struct ReplaceArgs;
+
+impl EntryTransform for ReplaceArgs {
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        job.command = crate::rules::replace_args(&job.rules, std::mem::take(&mut job.command));
+        if job.command.is_empty() {
+            return Err(anyhow::anyhow!("the command became empty"));
+        }
+        Ok(())
+    }
+}
+
+/// applies `--ccadd`
+pub struct AddArgs;
+
+impl EntryTransform for AddArgs {
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        for r in &job.rules {
+            job.command.extend_from_slice(&r.ccadd);
+        }
+        Ok(())
+    }
+}
+
+/// applies `--ccdel` and its variants
+pub struct DeleteArgs;
+
+impl EntryTransform for DeleteArgs {
+    fn apply(&self, job: &mut Job<'_>) -> Result<(), anyhow::Error> {
+        job.command = crate::rules::delete_args(&job.rules, std::mem::take(&mut job.command));
+        if job.command.is_empty() {
+            return Err(anyhow::anyhow!("the command became empty"));
+        }
+        Ok(())
+    }
+}
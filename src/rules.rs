@@ -0,0 +1,372 @@
+use crate::language::Language;
+use crate::options::{Command, Driver, ParsedArg};
+
+/// regex which has to match the whole argument
+#[derive(Debug)]
+pub struct AnchoredRegex(regex::Regex);
+
+impl std::str::FromStr for AnchoredRegex {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(regex::Regex::new(&format!("^(?:{s})$"))?))
+    }
+}
+
+impl AnchoredRegex {
+    pub fn is_match(&self, s: &str) -> bool {
+        self.0.is_match(s)
+    }
+}
+
+#[derive(Debug)]
+pub struct ReplaceRule {
+    from: regex::Regex,
+    to: String,
+}
+
+impl std::str::FromStr for ReplaceRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // split at the first `=` which isn't escaped
+        let pos = s
+            .char_indices()
+            .find(|&(i, c)| c == '=' && !s[..i].ends_with('\\'))
+            .map(|(i, _)| i)
+            .ok_or_else(|| anyhow::anyhow!("missing `=` in replace rule: {}", s))?;
+
+        Ok(Self {
+            from: regex::Regex::new(&s[..pos].replace("\\=", "="))?,
+            to: s[pos + 1..].to_string(),
+        })
+    }
+}
+
+impl ReplaceRule {
+    pub fn new(from: &str, to: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            from: regex::Regex::new(from)?,
+            to: to.to_string(),
+        })
+    }
+
+    pub fn apply(&self, arg: &str) -> String {
+        self.from.replace_all(arg, self.to.as_str()).to_string()
+    }
+
+    /// like `apply` but returns `None` if the regex doesn't match
+    pub fn apply_matching(&self, arg: &str) -> Option<String> {
+        if self.from.is_match(arg) {
+            Some(self.apply(arg))
+        } else {
+            None
+        }
+    }
+}
+
+/// deserializes an optional string using `FromStr`
+pub fn deserialize_parsed_opt<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let s: Option<String> = serde::Deserialize::deserialize(deserializer)?;
+    s.map(|s| s.parse().map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// deserializes a list of strings using `FromStr`
+pub fn deserialize_parsed_vec<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let v: Vec<String> = serde::Deserialize::deserialize(deserializer)?;
+    v.iter()
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+// argument rules which can be given on the command line, globally in the
+// config file or in a config file section. This isn't a doc comment because
+// clap would use it as the description of the application.
+#[derive(clap::Clap, serde::Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Rules {
+    /// additional C compiler arguments
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    pub ccadd: Vec<String>,
+
+    /// remove C compiler arguments
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    pub ccdel: Vec<String>,
+
+    /// remove C compiler arguments matching a regex. The regex has to match
+    /// the whole unescaped argument.
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub ccdel_regex: Vec<AnchoredRegex>,
+
+    /// remove C compiler arguments matching a glob pattern. The pattern is
+    /// matched against the unescaped argument.
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub ccdel_glob: Vec<glob::Pattern>,
+
+    /// remove a C compiler option together with its value, no matter if the
+    /// value is separate (`-include foo.h`) or joined (`-includefoo.h`).
+    /// Unknown options are assumed to take a separate value.
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    pub ccdel_with_value: Vec<String>,
+
+    /// replace C compiler arguments. The format is `FROM=TO` where FROM is a
+    /// regex and TO may refer to its capture groups, e.g. `$1`. A literal `=`
+    /// in FROM has to be written as `\=`. Arguments which become empty are
+    /// removed. The compiler isn't touched, `--use-cc` and friends replace it.
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub ccreplace: Vec<ReplaceRule>,
+
+    /// override C compiler
+    #[clap(long)]
+    pub use_cc: Option<String>,
+
+    /// override C++ compiler
+    #[clap(long)]
+    pub use_cxx: Option<String>,
+
+    /// override Objective-C compiler
+    #[clap(long)]
+    pub use_objc: Option<String>,
+
+    /// override Objective-C++ compiler
+    #[clap(long)]
+    pub use_objcxx: Option<String>,
+
+    /// override compiler used for assembler sources
+    #[clap(long)]
+    pub use_as: Option<String>,
+
+    /// override CUDA compiler, e.g. with clang since nvcc can't be probed
+    #[clap(long)]
+    pub use_cuda: Option<String>,
+}
+
+impl Rules {
+    /// combines the rules of two sources. Lists of `other` get appended and
+    /// its compiler overrides take precedence.
+    pub fn merge(mut self, other: Self) -> Self {
+        self.ccadd.extend(other.ccadd);
+        self.ccdel.extend(other.ccdel);
+        self.ccdel_regex.extend(other.ccdel_regex);
+        self.ccdel_glob.extend(other.ccdel_glob);
+        self.ccdel_with_value.extend(other.ccdel_with_value);
+        self.ccreplace.extend(other.ccreplace);
+        self.use_cc = other.use_cc.or(self.use_cc);
+        self.use_cxx = other.use_cxx.or(self.use_cxx);
+        self.use_objc = other.use_objc.or(self.use_objc);
+        self.use_objcxx = other.use_objcxx.or(self.use_objcxx);
+        self.use_as = other.use_as.or(self.use_as);
+        self.use_cuda = other.use_cuda.or(self.use_cuda);
+        self
+    }
+
+    pub fn has_compiler_override(&self) -> bool {
+        self.use_cc.is_some()
+            || self.use_cxx.is_some()
+            || self.use_objc.is_some()
+            || self.use_objcxx.is_some()
+            || self.use_as.is_some()
+            || self.use_cuda.is_some()
+    }
+
+    pub fn has_deletions(&self) -> bool {
+        !self.ccdel.is_empty()
+            || !self.ccdel_regex.is_empty()
+            || !self.ccdel_glob.is_empty()
+            || !self.ccdel_with_value.is_empty()
+    }
+
+    /// returns the compiler override for the given language
+    pub fn compiler_override(&self, language: Language) -> Option<&String> {
+        match language {
+            Language::C => self.use_cc.as_ref(),
+            Language::Cxx => self.use_cxx.as_ref(),
+            Language::ObjC => self.use_objc.as_ref(),
+            Language::ObjCxx => self.use_objcxx.as_ref(),
+            Language::Asm | Language::AsmCpp => self.use_as.as_ref(),
+            Language::Cuda => self.use_cuda.as_ref(),
+        }
+    }
+
+    fn is_deleted(&self, arg: &str) -> bool {
+        // the escaped form is supported for compatibility
+        self.ccdel
+            .iter()
+            .any(|del| del == arg || *del == crate::cdb::escape(arg))
+            || self.ccdel_regex.iter().any(|re| re.is_match(arg))
+            || self.ccdel_glob.iter().any(|pattern| pattern.matches(arg))
+    }
+}
+
+/// applies the `--ccreplace` rules of all given rule sets in order. The
+/// compiler is kept as it is.
+pub fn replace_args(rules: &[&Rules], args: Vec<String>) -> Vec<String> {
+    let mut args = args.into_iter();
+    args.next()
+        .into_iter()
+        .chain(args.map(|arg| {
+            rules
+                .iter()
+                .flat_map(|rules| rules.ccreplace.iter())
+                .fold(arg, |arg, rule| rule.apply(&arg))
+        }))
+        .filter(|arg| !arg.is_empty())
+        .collect()
+}
+
+/// removes all arguments matched by one of the `--ccdel*` options of any of
+/// the given rule sets. The compiler is never removed.
+pub fn delete_args(rules: &[&Rules], command: Vec<String>) -> Vec<String> {
+    let parsed = Command::parse(&command);
+    let mut deleted = vec![false; command.len()];
+
+    for (i, arg) in command.iter().enumerate().skip(1) {
+        deleted[i] = rules.iter().any(|rules| rules.is_deleted(arg));
+    }
+
+    let mut args = parsed.args.iter();
+    while let Some(arg) = args.next() {
+        let mut opts = rules.iter().flat_map(|rules| rules.ccdel_with_value.iter());
+        let value = if opts.clone().any(|opt| is_option(parsed.driver, arg, opt)) {
+            // the tables know where the value is
+            None
+        } else if opts.any(|opt| arg.raw[0] == *opt) {
+            // the option isn't known by this name, so its value is assumed
+            // to be the next argument
+            args.next()
+        } else {
+            continue;
+        };
+
+        for arg in std::iter::once(arg).chain(value) {
+            deleted[arg.index..arg.index + arg.raw.len()].fill(true);
+        }
+    }
+
+    command
+        .into_iter()
+        .zip(deleted)
+        .filter(|(_, deleted)| !deleted)
+        .map(|(arg, _)| arg)
+        .collect()
+}
+
+/// returns true if the argument is the option `name`. clang-cl options may
+/// be given using `-` as well, and `/Fo` also matches its `/Fo:` spelling.
+fn is_option(driver: Driver, arg: &ParsedArg<'_>, name: &str) -> bool {
+    if driver != Driver::Cl {
+        return arg.is(name);
+    }
+
+    // options only known from the gcc table keep their `-`
+    let slashed = match name.strip_prefix('-') {
+        Some(rest) if !rest.starts_with('-') => format!("/{}", rest),
+        _ => name.to_string(),
+    };
+    arg.is(name) || arg.is(&slashed) || arg.is(&format!("{}:", slashed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &str) -> Vec<String> {
+        args.split(' ').map(|arg| arg.to_string()).collect()
+    }
+
+    fn delete_with_value(opts: &[&str], args: &str) -> Vec<String> {
+        let rules = Rules {
+            ccdel_with_value: opts.iter().map(|opt| opt.to_string()).collect(),
+            ..Rules::default()
+        };
+        delete_args(&[&rules], command(args))
+    }
+
+    #[test]
+    fn delete_known_options_with_value() {
+        assert_eq!(
+            delete_with_value(
+                &["-include"],
+                "gcc -include a.h -includeb.h -include-pch x.pch -c a.c"
+            ),
+            command("gcc -include-pch x.pch -c a.c")
+        );
+        assert_eq!(
+            delete_with_value(&["-include-pch"], "gcc -include a.h -include-pch x.pch a.c"),
+            command("gcc -include a.h a.c")
+        );
+        // flags don't take a value
+        assert_eq!(delete_with_value(&["-c"], "gcc -c a.c"), command("gcc a.c"));
+        // clang-cl options can be given using `-` as well
+        assert_eq!(
+            delete_with_value(&["-FI", "/Fo"], "clang-cl /FIa.h -FI b.h /Fo:a.obj /c a.c"),
+            command("clang-cl /c a.c")
+        );
+    }
+
+    #[test]
+    fn delete_unknown_options_with_value() {
+        assert_eq!(
+            delete_with_value(&["-mfoo", "--bar"], "gcc -mfoo x --bar y -mfoox -c a.c"),
+            command("gcc -mfoox -c a.c")
+        );
+    }
+
+    #[test]
+    fn keep_compiler() {
+        let rules = Rules {
+            ccdel_glob: vec!["*gcc*".parse().unwrap()],
+            ccreplace: vec!["^.*$=".parse().unwrap()],
+            ..Rules::default()
+        };
+        assert_eq!(
+            delete_args(&[&rules], command("gcc -c a.c")),
+            ["gcc", "-c", "a.c"]
+        );
+        assert_eq!(replace_args(&[&rules], command("gcc -c a.c")), ["gcc"]);
+    }
+}
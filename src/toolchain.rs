@@ -0,0 +1,808 @@
+use crate::cache::DiskCache;
+use crate::language::Language;
+use crate::options::{ArgKind, Command, Driver, IncludeKind, Probe};
+use crate::rules::{deserialize_parsed_opt, deserialize_parsed_vec, ReplaceRule};
+
+/// the flag used to add toolchain includes to the command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IncludeFlag {
+    Isystem,
+    Idirafter,
+    Iquote,
+}
+
+impl std::str::FromStr for IncludeFlag {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim_start_matches('-') {
+            "isystem" => Ok(Self::Isystem),
+            "idirafter" => Ok(Self::Idirafter),
+            "iquote" => Ok(Self::Iquote),
+            _ => Err(anyhow::anyhow!("unsupported include flag: {}", s)),
+        }
+    }
+}
+
+impl IncludeFlag {
+    fn format(self, driver: Driver, path: &str) -> String {
+        match (driver, self) {
+            (Driver::Gcc, Self::Isystem) => format!("-isystem{path}"),
+            (Driver::Gcc, Self::Idirafter) => format!("-idirafter{path}"),
+            (Driver::Gcc, Self::Iquote) => format!("-iquote{path}"),
+            // clang-cl only has an equivalent of `-isystem`
+            (Driver::Cl, Self::Isystem) => format!("/imsvc{path}"),
+            (Driver::Cl, Self::Idirafter) => format!("/clang:-idirafter{path}"),
+            (Driver::Cl, Self::Iquote) => format!("/clang:-iquote{path}"),
+        }
+    }
+}
+
+/// where toolchain includes get inserted into the command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IncludePosition {
+    /// right after the compiler
+    Compiler,
+    /// before the first `-I`, at the end if there is none
+    BeforeIncludes,
+    /// at the end
+    End,
+}
+
+impl std::str::FromStr for IncludePosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compiler" => Ok(Self::Compiler),
+            "before-includes" => Ok(Self::BeforeIncludes),
+            "end" => Ok(Self::End),
+            _ => Err(anyhow::anyhow!("unsupported include position: {}", s)),
+        }
+    }
+}
+
+/// how toolchain defines are added to the command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DefinesMode {
+    /// `-U` and `-D` arguments
+    Args,
+    /// a generated header added using `-include`
+    Header,
+}
+
+impl std::str::FromStr for DefinesMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "args" => Ok(Self::Args),
+            "header" => Ok(Self::Header),
+            _ => Err(anyhow::anyhow!("unsupported defines mode: {}", s)),
+        }
+    }
+}
+
+// options for toolchain probing. This isn't a doc comment because clap would
+// use it as the description of the application.
+#[derive(clap::Clap, serde::Deserialize, Debug, Default)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct ToolchainOpts {
+    /// extracts the toolchain includes and adds them and `-nostdinc` to the
+    /// command
+    #[clap(long)]
+    pub resolve_toolchain_includes: bool,
+
+    /// where to insert the toolchain includes: compiler (right after the
+    /// compiler), before-includes (before the first `-I`) or end
+    /// [default: compiler]
+    #[clap(long)]
+    #[serde(deserialize_with = "deserialize_parsed_opt")]
+    pub toolchain_includes_position: Option<IncludePosition>,
+
+    /// flag used for the toolchains `#include <...>` search paths: isystem or
+    /// idirafter [default: isystem]
+    #[clap(long)]
+    #[serde(deserialize_with = "deserialize_parsed_opt")]
+    pub toolchain_includes_flag: Option<IncludeFlag>,
+
+    /// flag used for the toolchains `#include "..."` search paths: iquote,
+    /// isystem or idirafter [default: iquote]
+    #[clap(long)]
+    #[serde(deserialize_with = "deserialize_parsed_opt")]
+    pub toolchain_quote_includes_flag: Option<IncludeFlag>,
+
+    /// extracts the predefined macros of the toolchain and adds them to the
+    /// command
+    #[clap(long)]
+    pub resolve_toolchain_defines: bool,
+
+    /// how to add the toolchain defines: args (`-U` and `-D` for every macro)
+    /// or header (generated file added using `-include`) [default: args]
+    #[clap(long)]
+    #[serde(deserialize_with = "deserialize_parsed_opt")]
+    pub toolchain_defines_mode: Option<DefinesMode>,
+
+    /// directory for the headers generated by `--toolchain-defines-mode=header`
+    /// [default: `cdbpatch-defines` next to the patched database]
+    #[clap(long)]
+    pub toolchain_defines_dir: Option<String>,
+
+    /// adds `--target` to the command. The triple is taken from the name of
+    /// the compiler, e.g. `xtensa-esp32-elf-gcc`, or `-dumpmachine`.
+    #[clap(long)]
+    pub infer_target: bool,
+
+    /// maps a gcc triple to a clang triple. The format is `FROM=TO` like for
+    /// `--ccreplace`. These take precedence over the builtin mappings.
+    #[clap(
+        long,
+        allow_hyphen_values = true,
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    #[serde(deserialize_with = "deserialize_parsed_vec")]
+    pub target_map: Vec<ReplaceRule>,
+
+    /// caches the results of compiler invocations in `~/.cache/cdbpatch`, so
+    /// later runs don't have to spawn the compiler again
+    #[clap(long)]
+    pub probe_cache: bool,
+
+    /// directory of the probe cache, implies `--probe-cache`
+    #[clap(long)]
+    pub probe_cache_dir: Option<String>,
+}
+
+impl ToolchainOpts {
+    /// prepares the directory of the headers generated by
+    /// `--toolchain-defines-mode=header`. It defaults to `cdbpatch-defines`
+    /// next to the patched database `out`. The directory gets created and
+    /// made absolute since the headers are used from within the directory of
+    /// every entry.
+    pub fn init_defines_dir(&mut self, out: &str) -> Result<(), anyhow::Error> {
+        if self.toolchain_defines_mode != Some(DefinesMode::Header) {
+            return Ok(());
+        }
+
+        let dir = match &self.toolchain_defines_dir {
+            Some(dir) => std::path::PathBuf::from(dir),
+            None => std::path::Path::new(out).with_file_name("cdbpatch-defines"),
+        };
+        std::fs::create_dir_all(&dir)?;
+        self.toolchain_defines_dir = Some(dir.canonicalize()?.to_string_lossy().to_string());
+
+        Ok(())
+    }
+
+    /// combines the options of two sources, the ones of `other` take
+    /// precedence
+    pub fn merge(self, other: Self) -> Self {
+        // the target mappings of `other` come first since the first matching
+        // one wins
+        Self {
+            resolve_toolchain_includes: self.resolve_toolchain_includes
+                || other.resolve_toolchain_includes,
+            toolchain_includes_position: other
+                .toolchain_includes_position
+                .or(self.toolchain_includes_position),
+            toolchain_includes_flag: other
+                .toolchain_includes_flag
+                .or(self.toolchain_includes_flag),
+            toolchain_quote_includes_flag: other
+                .toolchain_quote_includes_flag
+                .or(self.toolchain_quote_includes_flag),
+            resolve_toolchain_defines: self.resolve_toolchain_defines
+                || other.resolve_toolchain_defines,
+            toolchain_defines_mode: other.toolchain_defines_mode.or(self.toolchain_defines_mode),
+            toolchain_defines_dir: other.toolchain_defines_dir.or(self.toolchain_defines_dir),
+            infer_target: self.infer_target || other.infer_target,
+            target_map: other
+                .target_map
+                .into_iter()
+                .chain(self.target_map)
+                .collect(),
+            probe_cache: self.probe_cache || other.probe_cache,
+            probe_cache_dir: other.probe_cache_dir.or(self.probe_cache_dir),
+        }
+    }
+}
+
+/// the include search paths reported by the compiler, in search order
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+struct ToolchainIncludes {
+    /// paths searched by `#include "..."` only
+    quote: Vec<String>,
+    /// paths searched by `#include <...>`
+    system: Vec<String>,
+}
+
+impl ToolchainIncludes {
+    /// parses the `-v` output of the preprocessor
+    fn parse(output: &str) -> Self {
+        let mut ret = Self::default();
+        let mut group = None;
+
+        for line in output.lines() {
+            if line.starts_with("#include \"...\"") {
+                group = Some(&mut ret.quote);
+            } else if line.starts_with("#include <...>") {
+                group = Some(&mut ret.system);
+            } else if line.starts_with("End of search list") {
+                break;
+            } else if let (Some(group), Some(path)) = (&mut group, line.strip_prefix(' ')) {
+                // frameworks can't be added using include flags
+                if !path.ends_with("(framework directory)") {
+                    group.push(path.to_string());
+                }
+            }
+        }
+
+        ret
+    }
+
+    fn add_to(&self, opts: &ToolchainOpts, command: &mut Vec<String>) {
+        let quote_flag = opts
+            .toolchain_quote_includes_flag
+            .unwrap_or(IncludeFlag::Iquote);
+        let system_flag = opts.toolchain_includes_flag.unwrap_or(IncludeFlag::Isystem);
+        let driver = Driver::detect(command);
+        let includes = self
+            .quote
+            .iter()
+            .map(|path| quote_flag.format(driver, path))
+            .chain(
+                self.system
+                    .iter()
+                    .map(|path| system_flag.format(driver, path)),
+            );
+
+        let pos = match opts
+            .toolchain_includes_position
+            .unwrap_or(IncludePosition::Compiler)
+        {
+            IncludePosition::Compiler => 1,
+            IncludePosition::BeforeIncludes => Command::parse(command)
+                .args
+                .iter()
+                .find(|arg| arg.kind == ArgKind::Include(IncludeKind::User))
+                .map_or(command.len(), |arg| arg.index),
+            IncludePosition::End => command.len(),
+        };
+        command.splice(pos..pos, includes);
+    }
+}
+
+/// gcc triples which clang doesn't understand or where it would pick a
+/// different configuration
+static BUILTIN_TARGET_MAP: &[(&str, &str)] = &[
+    (r"^(x86_64|i[3-6]86)-linux-gnu$", "${1}-pc-linux-gnu"),
+    (
+        r"^(\w+)-linux-(gnu\w*|musl\w*|android\w*)$",
+        "${1}-unknown-linux-$2",
+    ),
+    (r"^(\w+)-w64-mingw32$", "${1}-w64-windows-gnu"),
+    (r"^xtensa-esp\w*-elf$", "xtensa-esp-elf"),
+    (r"^(\w+)-elf$", "${1}-unknown-elf"),
+];
+
+/// returns the lowercase file name of a compiler without `.exe`
+fn compiler_name(compiler: &str) -> String {
+    let name = compiler
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap()
+        .to_ascii_lowercase();
+    match name.strip_suffix(".exe") {
+        Some(name) => name.to_string(),
+        None => name,
+    }
+}
+
+/// returns true for clang and cross compilers based on it
+fn is_clang(compiler: &str) -> bool {
+    compiler_name(compiler).contains("clang")
+}
+
+/// returns true for nvcc, which doesn't support the options used for probing
+pub fn is_nvcc(compiler: &str) -> bool {
+    compiler_name(compiler) == "nvcc"
+}
+
+/// returns the triple prefix of a cross compiler's name, e.g. `arm-none-eabi`
+/// for `/opt/bin/arm-none-eabi-gcc-10.3`
+fn triple_from_compiler_name(compiler: &str) -> Option<String> {
+    lazy_static::lazy_static! {
+        static ref DRIVER_PATTERN: regex::Regex = regex::Regex::new(
+            r"^(.+)-(gcc|g\+\+|cc|c\+\+|cpp|clang|clang\+\+)(-[0-9.]+)?(\.exe)?$"
+        )
+        .unwrap();
+    }
+
+    let name = std::path::Path::new(compiler).file_name()?.to_str()?;
+    let triple = &DRIVER_PATTERN.captures(name)?[1];
+
+    // a triple consists of at least two components
+    if triple.contains('-') {
+        Some(triple.to_string())
+    } else {
+        None
+    }
+}
+
+/// maps a gcc triple to the closest clang triple
+fn map_target(opts: &ToolchainOpts, triple: &str) -> String {
+    lazy_static::lazy_static! {
+        static ref BUILTIN: Vec<ReplaceRule> = BUILTIN_TARGET_MAP
+            .iter()
+            .map(|(from, to)| ReplaceRule::new(from, to).unwrap())
+            .collect();
+    }
+
+    opts.target_map
+        .iter()
+        .chain(BUILTIN.iter())
+        .find_map(|rule| rule.apply_matching(triple))
+        .unwrap_or_else(|| triple.to_string())
+}
+
+/// returns true if the path is relative and contains a directory, i.e. it
+/// isn't searched in `PATH`
+fn is_relative_compiler(compiler: &str) -> bool {
+    let path = std::path::Path::new(compiler);
+    path.is_relative() && path.components().count() > 1
+}
+
+/// everything a probe depends on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ProbeKey {
+    args: Vec<String>,
+    /// the working directory, only set if the args contain relative paths
+    directory: Option<String>,
+}
+
+impl ProbeKey {
+    fn new(args: Vec<String>, directory: &str, relative: bool) -> Self {
+        Self {
+            args,
+            directory: if relative {
+                Some(directory.to_string())
+            } else {
+                None
+            },
+        }
+    }
+
+    /// the compiler, resolved like the build would have done it
+    fn compiler(&self) -> String {
+        match &self.directory {
+            Some(directory) if is_relative_compiler(&self.args[0]) => {
+                std::path::Path::new(directory)
+                    .join(&self.args[0])
+                    .to_string_lossy()
+                    .to_string()
+            }
+            _ => self.args[0].clone(),
+        }
+    }
+
+    /// the key used for the disk cache
+    fn disk_key(&self) -> Option<Vec<String>> {
+        let identity = DiskCache::compiler_identity(&self.compiler())?;
+        Some(
+            self.args
+                .iter()
+                .cloned()
+                .chain(self.directory.iter().cloned())
+                .chain(identity)
+                .collect(),
+        )
+    }
+}
+
+/// runs the compiler and returns its stdout
+fn run_compiler(key: &ProbeKey) -> Result<std::process::Output, anyhow::Error> {
+    let args = &key.args;
+    let mut cmd = std::process::Command::new(key.compiler());
+    if let Some(directory) = &key.directory {
+        cmd.current_dir(directory);
+    }
+    cmd.args(&args[1..])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    let output = cmd
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("can't run {}: {}", key.compiler(), e))?
+        .wait_with_output()?;
+    if !output.status.success() {
+        let stderr = std::str::from_utf8(&output.stderr);
+        return match stderr {
+            Ok(stderr) => Err(anyhow::anyhow!(
+                "compiler failed ({}). args: {:?}\nstderr:\n {}",
+                output.status,
+                args,
+                stderr
+            )),
+            Err(_) => Err(anyhow::anyhow!(
+                "compiler failed ({}). args: {:?}\nstderr:\n {:?}",
+                output.status,
+                args,
+                &output.stderr
+            )),
+        };
+    }
+
+    Ok(output)
+}
+
+/// a predefined macro
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+struct Define {
+    /// name including the parameters of function-like macros
+    name: String,
+    value: String,
+}
+
+impl Define {
+    /// prefixes of macros which can't or shouldn't be undefined. The standard
+    /// ones are defined by every compiler anyway.
+    const SKIP_PREFIXES: &'static [&'static str] = &["__STDC", "__cplusplus", "__has_include"];
+
+    /// parses the `-dM` output of the preprocessor
+    fn parse_all(output: &str) -> Vec<Self> {
+        output
+            .lines()
+            .filter_map(|line| line.strip_prefix("#define "))
+            .filter_map(|line| {
+                // the parameter list of function-like macros doesn't contain
+                // spaces in the `-dM` output
+                let (name, value) = line.split_once(' ').unwrap_or((line, ""));
+                let ident = name.split('(').next().unwrap();
+                if Self::SKIP_PREFIXES.iter().any(|p| ident.starts_with(p)) {
+                    return None;
+                }
+
+                Some(Self {
+                    name: name.to_string(),
+                    value: value.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    fn ident(&self) -> &str {
+        self.name.split('(').next().unwrap()
+    }
+}
+
+/// everything we found out about a toolchain configuration
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, Clone)]
+struct ToolchainInfo {
+    includes: ToolchainIncludes,
+    defines: Vec<Define>,
+    /// generated header containing the defines
+    #[serde(skip)]
+    defines_header: Option<String>,
+}
+
+impl ToolchainInfo {
+    fn add_to(&self, opts: &ToolchainOpts, command: &mut Vec<String>) {
+        if opts.resolve_toolchain_includes {
+            self.includes.add_to(opts, command);
+        }
+
+        if !opts.resolve_toolchain_defines {
+            return;
+        }
+
+        // added right after the compiler, so defines of the command win
+        let defines = match (&self.defines_header, Driver::detect(command)) {
+            (Some(header), Driver::Gcc) => vec!["-include".to_string(), header.to_string()],
+            (Some(header), Driver::Cl) => vec![format!("/FI{header}")],
+            (None, _) => self
+                .defines
+                .iter()
+                .flat_map(|define| {
+                    // undefine first, so compilers with a different set of
+                    // builtin macros don't warn about redefinitions
+                    [
+                        format!("-U{}", define.ident()),
+                        format!("-D{}={}", define.name, define.value),
+                    ]
+                })
+                .collect(),
+        };
+        command.splice(1..1, defines);
+    }
+
+    /// writes the defines into a header within `dir`. The name is derived from
+    /// the probe args so equal configurations share one header.
+    fn write_defines_header(&mut self, dir: &str, key: &ProbeKey) -> Result<(), anyhow::Error> {
+        use std::hash::{Hash as _, Hasher as _};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        let path = std::path::Path::new(dir).join(format!("defines-{:016x}.h", hasher.finish()));
+
+        let mut content = String::from("#pragma once\n");
+        for define in &self.defines {
+            content.push_str(&format!(
+                "#undef {}\n#define {} {}\n",
+                define.ident(),
+                define.name,
+                define.value
+            ));
+        }
+
+        // other threads might write the same header at the same time
+        let tmp = path.with_extension(format!("h.{:?}.tmp", std::thread::current().id()));
+        std::fs::write(&tmp, content)?;
+        std::fs::rename(&tmp, &path)?;
+
+        self.defines_header = Some(path.to_string_lossy().to_string());
+        Ok(())
+    }
+}
+
+/// result of a probe. Errors are stored as messages so every entry using the
+/// probe can report them.
+type ProbeResult<T> = Result<T, String>;
+
+pub struct ToolchainInfoCache {
+    hm: std::sync::Mutex<
+        std::collections::HashMap<ProbeKey, ProbeResult<std::sync::Arc<ToolchainInfo>>>,
+    >,
+    /// clang triple by compiler
+    targets: std::sync::Mutex<std::collections::HashMap<ProbeKey, ProbeResult<String>>>,
+    disk: Option<DiskCache>,
+}
+
+impl ToolchainInfoCache {
+    pub fn new(opts: &ToolchainOpts) -> Result<Self, anyhow::Error> {
+        let disk = match (&opts.probe_cache_dir, opts.probe_cache) {
+            (Some(dir), _) => Some(DiskCache::new(dir.into())?),
+            (None, true) => {
+                let dir = DiskCache::default_dir()
+                    .ok_or_else(|| anyhow::anyhow!("can't determine the cache directory"))?;
+                Some(DiskCache::new(dir)?)
+            }
+            (None, false) => None,
+        };
+
+        Ok(Self {
+            hm: Default::default(),
+            targets: Default::default(),
+            disk,
+        })
+    }
+
+    /// runs the compiler unless the result is in the disk cache. `parse`
+    /// turns the output into the cached value.
+    fn run_cached<T, F>(&self, key: &ProbeKey, parse: F) -> Result<T, anyhow::Error>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+        F: FnOnce(std::process::Output) -> Result<T, anyhow::Error>,
+    {
+        let disk_key = self.disk.as_ref().and_then(|_| key.disk_key());
+
+        if let (Some(disk), Some(disk_key)) = (&self.disk, &disk_key) {
+            if let Some(value) = disk.get(disk_key) {
+                return Ok(value);
+            }
+        }
+
+        let value = parse(run_compiler(key)?)?;
+
+        if let (Some(disk), Some(disk_key)) = (&self.disk, &disk_key) {
+            disk.put(disk_key, &value)?;
+        }
+
+        Ok(value)
+    }
+
+    /// returns true if the command selects a target already
+    pub fn has_target(command: &[String]) -> bool {
+        Command::parse(command).has_target()
+    }
+
+    /// returns the key for inferring the target of the command's compiler
+    pub fn target_key(command: &[String], directory: &str) -> ProbeKey {
+        ProbeKey::new(
+            vec![command[0].clone(), "-dumpmachine".to_string()],
+            directory,
+            is_relative_compiler(&command[0]),
+        )
+    }
+
+    fn probe_target(&self, opts: &ToolchainOpts, key: &ProbeKey) -> Result<String, anyhow::Error> {
+        let triple = match triple_from_compiler_name(&key.args[0]) {
+            Some(triple) => triple,
+            None => self.run_cached(key, |output| {
+                Ok(std::str::from_utf8(&output.stdout)?.trim().to_string())
+            })?,
+        };
+
+        Ok(map_target(opts, &triple))
+    }
+
+    fn target(&self, opts: &ToolchainOpts, key: &ProbeKey) -> ProbeResult<String> {
+        if let Some(target) = self.targets.lock().unwrap().get(key) {
+            return target.clone();
+        }
+
+        let target = self.probe_target(opts, key).map_err(|e| format!("{e:#}"));
+        self.targets
+            .lock()
+            .unwrap()
+            .insert(key.clone(), target.clone());
+        target
+    }
+
+    /// infers the targets of all given compilers in parallel
+    pub fn probe_targets<'a, I>(&self, opts: &ToolchainOpts, keys: I)
+    where
+        I: rayon::iter::IntoParallelIterator<Item = &'a ProbeKey>,
+    {
+        use rayon::iter::ParallelIterator as _;
+
+        keys.into_par_iter().for_each(|key| {
+            self.target(opts, key).ok();
+        });
+    }
+
+    /// adds `--target` for the compiler of the command unless there is one
+    /// already
+    pub fn add_target(
+        &self,
+        opts: &ToolchainOpts,
+        directory: &str,
+        command: &mut Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        if Self::has_target(command) {
+            return Ok(());
+        }
+
+        let key = Self::target_key(command, directory);
+        let target = self.target(opts, &key).map_err(anyhow::Error::msg)?;
+        command.insert(1, format!("--target={target}"));
+        Ok(())
+    }
+
+    /// returns the normalized probe for a command. Commands with the same
+    /// probe key share the same toolchain configuration.
+    pub fn probe_key(
+        language: Language,
+        opts: &ToolchainOpts,
+        command: &[String],
+        directory: &str,
+    ) -> ProbeKey {
+        let mut relative = is_relative_compiler(&command[0]);
+        let parsed = Command::parse(command);
+        let driver = parsed.driver;
+
+        // keep the options which matter for the cpp in command line order,
+        // since later options like `-O0` or `-UFOO` override earlier ones
+        let groups: Vec<_> = parsed
+            .args
+            .into_iter()
+            .filter(|arg| match arg.probe() {
+                Probe::Ignore => false,
+                Probe::Toolchain => true,
+                Probe::Defines => opts.resolve_toolchain_defines,
+            })
+            .inspect(|arg| {
+                relative |= arg
+                    .path()
+                    .is_some_and(|path| std::path::Path::new(path).is_relative());
+            })
+            .map(|arg| arg.raw)
+            .collect();
+
+        let mut args = Vec::with_capacity(command.len());
+        args.push(command[0].to_string());
+        args.extend(groups.into_iter().flatten().cloned());
+
+        // since we're using /dev/null we have to set the language. `-v`
+        // prints the includes to stderr, `-dM` the defines to stdout.
+        match driver {
+            Driver::Gcc => {
+                // only clang knows `-xcuda`, the host part of CUDA is C++
+                let language = match language {
+                    Language::Cuda if !is_clang(&command[0]) => Language::Cxx,
+                    language => language,
+                };
+                args.push(format!("-x{}", language.name()));
+                args.extend(
+                    ["-P", "-E", "-dM", "-Wp,-v", "/dev/null"]
+                        .iter()
+                        .map(|s| s.to_string()),
+                );
+            }
+            Driver::Cl => {
+                // clang-cl only knows about C and C++
+                let language = match language {
+                    Language::Cxx | Language::ObjCxx | Language::Cuda => "/TP",
+                    _ => "/TC",
+                };
+                args.extend(
+                    [language, "/E", "-v", "/clang:-dM", "--", "/dev/null"]
+                        .iter()
+                        .map(|s| s.to_string()),
+                );
+            }
+        }
+
+        ProbeKey::new(args, directory, relative)
+    }
+
+    fn probe(&self, opts: &ToolchainOpts, key: &ProbeKey) -> Result<ToolchainInfo, anyhow::Error> {
+        let mut info = self.run_cached(key, |output| {
+            Ok(ToolchainInfo {
+                includes: ToolchainIncludes::parse(std::str::from_utf8(&output.stderr)?),
+                defines: Define::parse_all(std::str::from_utf8(&output.stdout)?),
+                defines_header: None,
+            })
+        })?;
+        if opts.resolve_toolchain_defines
+            && opts.toolchain_defines_mode == Some(DefinesMode::Header)
+        {
+            let dir = opts
+                .toolchain_defines_dir
+                .as_ref()
+                .ok_or_else(|| anyhow::anyhow!("no directory for the defines header"))?;
+            info.write_defines_header(dir, key)?;
+        }
+
+        Ok(info)
+    }
+
+    fn info(
+        &self,
+        opts: &ToolchainOpts,
+        key: &ProbeKey,
+    ) -> ProbeResult<std::sync::Arc<ToolchainInfo>> {
+        if let Some(info) = self.hm.lock().unwrap().get(key) {
+            return info.clone();
+        }
+
+        let info = self
+            .probe(opts, key)
+            .map(std::sync::Arc::new)
+            .map_err(|e| format!("{e:#}"));
+        self.hm.lock().unwrap().insert(key.clone(), info.clone());
+        info
+    }
+
+    /// runs all given probes in parallel. Afterwards `add_toolchain_info`
+    /// doesn't have to spawn the compiler for them anymore.
+    pub fn probe_all<'a, I>(&self, opts: &ToolchainOpts, probes: I)
+    where
+        I: rayon::iter::IntoParallelIterator<Item = &'a ProbeKey>,
+    {
+        use rayon::iter::ParallelIterator as _;
+
+        probes.into_par_iter().for_each(|key| {
+            self.info(opts, key).ok();
+        });
+    }
+
+    /// the number of distinct toolchain configurations probed so far
+    pub fn configurations(&self) -> usize {
+        self.hm.lock().unwrap().len()
+    }
+
+    /// adds the toolchain includes and defines enabled by `opts` to the
+    /// command
+    pub fn add_toolchain_info(
+        &self,
+        language: Language,
+        opts: &ToolchainOpts,
+        directory: &str,
+        command: &mut Vec<String>,
+    ) -> Result<(), anyhow::Error> {
+        let key = Self::probe_key(language, opts, command, directory);
+        let info = self.info(opts, &key).map_err(anyhow::Error::msg)?;
+        info.add_to(opts, command);
+        Ok(())
+    }
+}
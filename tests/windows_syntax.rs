@@ -0,0 +1,79 @@
+use cdbpatch::cdb::{self, CdbEntry, CommandSyntax, OutputFormat};
+use cdbpatch::config::Config;
+use cdbpatch::pipeline::Pipeline;
+
+const FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/windows.json");
+
+fn args(entry: &CdbEntry) -> Vec<String> {
+    entry.args(CommandSyntax::Auto).unwrap()
+}
+
+#[test]
+fn split_fixture() {
+    let cdb = cdb::load(FIXTURE).unwrap();
+
+    assert_eq!(
+        args(&cdb[0]),
+        [
+            "clang-cl.exe",
+            "/nologo",
+            "/c",
+            r"/IC:\Program Files\SDK\include",
+            r#"/DNAME="value""#,
+            r#"/DPATH="C:\dir with space\""#,
+            r"src\main.c",
+            r"/Fo:obj\main.obj",
+        ]
+    );
+    assert_eq!(
+        args(&cdb[1]),
+        [
+            "cl.exe",
+            "/c",
+            "/EHsc",
+            "/DEMPTY=",
+            "",
+            r#"/DQUOTE=""#,
+            r"src\util.cpp",
+            r"/FoC:\out dir\",
+        ]
+    );
+    assert_eq!(
+        args(&cdb[2]),
+        ["clang", "--driver-mode=cl", "/c", r"src\gnu.c", "/DX=a b"]
+    );
+}
+
+#[test]
+fn fixture_round_trip() {
+    let original = cdb::load(FIXTURE).unwrap();
+    let config = Config {
+        output_format: Some(OutputFormat::Command),
+        ..Config::default()
+    };
+
+    // an empty pipeline splits and escapes every command again
+    let mut patched = original.clone();
+    for result in Pipeline::new(&config).run(&mut patched) {
+        result.unwrap();
+    }
+
+    let path = std::env::temp_dir().join("cdbpatch-windows-round-trip.json");
+    let path = path.to_str().unwrap();
+    cdb::save(path, &patched).unwrap();
+    let reloaded = cdb::load(path).unwrap();
+    std::fs::remove_file(path).unwrap();
+
+    for (original, reloaded) in original.iter().zip(&reloaded) {
+        assert_eq!(args(original), args(reloaded));
+    }
+
+    // escaping the escaped commands again doesn't change them
+    let mut repatched = reloaded.clone();
+    for result in Pipeline::new(&config).run(&mut repatched) {
+        result.unwrap();
+    }
+    for (reloaded, repatched) in reloaded.iter().zip(&repatched) {
+        assert_eq!(reloaded.command, repatched.command);
+    }
+}